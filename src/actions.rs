@@ -0,0 +1,269 @@
+/*
+
+Actions! Gameplay and menus never look at KeyCodes directly, they ask Input<Action> instead
+(same just_pressed/pressed as Input<KeyCode>, just with our own names). Which keys and gamepad
+buttons trigger which action lives in the Bindings resource, which gets saved to bindings.ron
+whenever it's changed from the Options screen. Any connected gamepad works, and its left stick
+both presses the Move actions and shows up as MoveStick for proper analog movement. The right
+stick does the same for the Aim actions (AimStick), which only matter in twin stick mode.
+
+*/
+
+use std::{collections::HashMap, fs};
+
+use bevy::{
+    prelude::*,
+    input::{
+        gamepad::{GamepadEvent, GamepadEventType},
+        InputSystem,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+const BINDINGS_FILE : &str = "bindings.ron";
+// Stick travel (0 to 1) that gets ignored, worn sticks never quite sit at the centre
+const DEADZONE : f32 = 0.25;
+// How far the stick has to be pushed to count as pressing a Move action, for menus and aiming
+const STICK_PRESS : f32 = 0.5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    AimUp, // The Aim actions point the player without moving them, in twin stick mode
+    AimDown,
+    AimLeft,
+    AimRight,
+    Fire,
+    AimLock, // Holding it keeps the player facing the same way while moving, same as holding Fire
+    Pause,
+    Confirm, // Picks the selected menu button
+    Back,   // Leaves the current menu
+}
+
+impl Action {
+    // Every action, in the order the Options screen lists them
+    pub const ALL : [Action; 13] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::AimUp,
+        Action::AimDown,
+        Action::AimLeft,
+        Action::AimRight,
+        Action::Fire,
+        Action::AimLock,
+        Action::Pause,
+        Action::Confirm,
+        Action::Back,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move Up",
+            Action::MoveDown => "Move Down",
+            Action::MoveLeft => "Move Left",
+            Action::MoveRight => "Move Right",
+            Action::AimUp => "Aim Up",
+            Action::AimDown => "Aim Down",
+            Action::AimLeft => "Aim Left",
+            Action::AimRight => "Aim Right",
+            Action::Fire => "Fire",
+            Action::AimLock => "Aim Lock",
+            Action::Pause => "Pause",
+            Action::Confirm => "Confirm",
+            Action::Back => "Back",
+        }
+    }
+}
+
+// Which keys and gamepad buttons trigger each action. Any of them will do.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    keys : HashMap<Action, Vec<KeyCode>>,
+    #[serde(default)]
+    buttons : HashMap<Action, Vec<GamepadButtonType>>,
+    // Aim with the Aim actions (or the right stick) instead of whichever way you're moving
+    #[serde(default)]
+    pub twin_stick : bool,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(Action::MoveUp, vec![KeyCode::Up]);
+        keys.insert(Action::MoveDown, vec![KeyCode::Down]);
+        keys.insert(Action::MoveLeft, vec![KeyCode::Left]);
+        keys.insert(Action::MoveRight, vec![KeyCode::Right]);
+        keys.insert(Action::AimUp, vec![KeyCode::W]);
+        keys.insert(Action::AimDown, vec![KeyCode::S]);
+        keys.insert(Action::AimLeft, vec![KeyCode::A]);
+        keys.insert(Action::AimRight, vec![KeyCode::D]);
+        keys.insert(Action::Fire, vec![KeyCode::Z]);
+        keys.insert(Action::AimLock, vec![KeyCode::LShift]);
+        keys.insert(Action::Pause, vec![KeyCode::Escape]);
+        keys.insert(Action::Confirm, vec![KeyCode::Z, KeyCode::Return]);
+        keys.insert(Action::Back, vec![KeyCode::X]);
+        let mut buttons = HashMap::new();
+        buttons.insert(Action::MoveUp, vec![GamepadButtonType::DPadUp]);
+        buttons.insert(Action::MoveDown, vec![GamepadButtonType::DPadDown]);
+        buttons.insert(Action::MoveLeft, vec![GamepadButtonType::DPadLeft]);
+        buttons.insert(Action::MoveRight, vec![GamepadButtonType::DPadRight]);
+        buttons.insert(Action::Fire, vec![GamepadButtonType::South, GamepadButtonType::RightTrigger2]);
+        buttons.insert(Action::AimLock, vec![GamepadButtonType::LeftTrigger]);
+        buttons.insert(Action::Pause, vec![GamepadButtonType::Start]);
+        buttons.insert(Action::Confirm, vec![GamepadButtonType::South]);
+        buttons.insert(Action::Back, vec![GamepadButtonType::East]);
+        Bindings {
+            keys,
+            buttons,
+            twin_stick : false,
+        }
+    }
+}
+
+impl Bindings {
+    // Whatever's saved in bindings.ron, with the defaults filling in anything it's missing
+    pub fn load() -> Self {
+        let mut bindings = Bindings::default();
+        let saved = match fs::read_to_string(BINDINGS_FILE) {
+            Ok(saved) => saved,
+            Err(_) => return bindings, // First run, nothing saved yet
+        };
+        match ron::de::from_str::<Bindings>(&saved) {
+            Ok(saved) => {
+                bindings.keys.extend(saved.keys);
+                bindings.buttons.extend(saved.buttons);
+                bindings.twin_stick = saved.twin_stick;
+            },
+            Err(error) => warn!("Ignoring {}, couldn't read it: {}", BINDINGS_FILE, error),
+        }
+        bindings
+    }
+
+    pub fn save(&self) {
+        let saved = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())
+            .and_then(|saved| fs::write(BINDINGS_FILE, saved).map_err(|error| error.to_string()));
+        if let Err(error) = saved {
+            warn!("Couldn't save {}: {}", BINDINGS_FILE, error);
+        }
+    }
+
+    pub fn keys(&self, action : Action) -> &[KeyCode] {
+        self.keys.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn buttons(&self, action : Action) -> &[GamepadButtonType] {
+        self.buttons.get(&action).map_or(&[], |buttons| buttons.as_slice())
+    }
+
+    // Replaces every key bound to the action with just this one. Gamepad buttons are left alone.
+    pub fn rebind(&mut self, action : Action, key : KeyCode) {
+        self.keys.insert(action, vec![key]);
+    }
+
+    // Same as rebind, but for gamepad buttons
+    pub fn rebind_button(&mut self, action : Action, button : GamepadButtonType) {
+        self.buttons.insert(action, vec![button]);
+    }
+}
+
+// Every gamepad that's plugged in right now
+#[derive(Default)]
+pub struct Gamepads(pub Vec<Gamepad>);
+
+// Where the left stick is pushed, deadzone already taken out. Length goes from 0 up to 1.
+#[derive(Default)]
+pub struct MoveStick(pub Vec2);
+
+// Same thing for the right stick
+#[derive(Default)]
+pub struct AimStick(pub Vec2);
+
+fn track_gamepads(mut events : EventReader<GamepadEvent>, mut gamepads : ResMut<Gamepads>) {
+    for GamepadEvent(gamepad, event) in events.iter() {
+        match event {
+            GamepadEventType::Connected => {
+                info!("{:?} connected", gamepad);
+                gamepads.0.push(*gamepad);
+            },
+            GamepadEventType::Disconnected => {
+                info!("{:?} disconnected", gamepad);
+                gamepads.0.retain(|connected| connected != gamepad);
+            },
+            _ => (),
+        }
+    }
+}
+
+// The first stick that's pushed past the deadzone, rescaled so it starts from 0 right at the edge of it
+fn read_stick(gamepads : &Gamepads, axes : &Axis<GamepadAxis>, x_axis : GamepadAxisType, y_axis : GamepadAxisType) -> Vec2 {
+    for &gamepad in gamepads.0.iter() {
+        let x = axes.get(GamepadAxis(gamepad, x_axis)).unwrap_or(0.0);
+        let y = axes.get(GamepadAxis(gamepad, y_axis)).unwrap_or(0.0);
+        let stick = Vec2::new(x, y);
+        let length = stick.length();
+        if length > DEADZONE {
+            return stick / length * ((length - DEADZONE) / (1.0 - DEADZONE)).min(1.0);
+        }
+    }
+    Vec2::ZERO
+}
+
+// Presses and releases actions to match the keys and buttons bound to them. Runs right after bevy reads the keyboard and gamepads.
+fn update_actions(
+    keys : Res<Input<KeyCode>>,
+    buttons : Res<Input<GamepadButton>>,
+    axes : Res<Axis<GamepadAxis>>,
+    gamepads : Res<Gamepads>,
+    bindings : Res<Bindings>,
+    mut stick : ResMut<MoveStick>,
+    mut aim_stick : ResMut<AimStick>,
+    mut actions : ResMut<Input<Action>>,
+) {
+    stick.0 = read_stick(&gamepads, &axes, GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+    aim_stick.0 = read_stick(&gamepads, &axes, GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+    actions.update();
+    for &action in Action::ALL.iter() {
+        let stick_held = match action {
+            Action::MoveUp => stick.0.y > STICK_PRESS,
+            Action::MoveDown => stick.0.y < -STICK_PRESS,
+            Action::MoveLeft => stick.0.x < -STICK_PRESS,
+            Action::MoveRight => stick.0.x > STICK_PRESS,
+            Action::AimUp => aim_stick.0.y > STICK_PRESS,
+            Action::AimDown => aim_stick.0.y < -STICK_PRESS,
+            Action::AimLeft => aim_stick.0.x < -STICK_PRESS,
+            Action::AimRight => aim_stick.0.x > STICK_PRESS,
+            _ => false,
+        };
+        let held = stick_held
+            || bindings.keys(action).iter().any(|&key| keys.pressed(key))
+            || bindings.buttons(action).iter().any(|&button| {
+                gamepads.0.iter().any(|&gamepad| buttons.pressed(GamepadButton(gamepad, button)))
+            });
+        if held && !actions.pressed(action) {
+            actions.press(action);
+        } else if !held && actions.pressed(action) {
+            actions.release(action);
+        }
+    }
+}
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .insert_resource(Bindings::load())
+            .init_resource::<Input<Action>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<MoveStick>()
+            .init_resource::<AimStick>()
+            .add_system_to_stage(CoreStage::PreUpdate, track_gamepads.system().label("track_gamepads").after(InputSystem))
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.system().after("track_gamepads"));
+    }
+}
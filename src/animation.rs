@@ -0,0 +1,156 @@
+/*
+
+The "up, down, left, right, hurt, dead, etc sprite" system from the top of main.rs.
+Anything animated has a named state for each of those, and each state is a run of frames
+out of the entity's sprite sheet. animate() works out which state an entity should be in
+from its Direction, Health and whether it's Hurt, then steps through that state's frames.
+The states and their frames come from the entity's prefab.
+
+*/
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use super::{Direction, DirectionEnum, Health, Hurt};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub enum AnimationState {
+    Up,
+    Down,
+    Left,
+    Right,
+    Hurt,
+    Dead,
+}
+
+impl AnimationState {
+    // Matches tag names in .aseprite files, which artists tend to write in lowercase
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "up" => Some(AnimationState::Up),
+            "down" => Some(AnimationState::Down),
+            "left" => Some(AnimationState::Left),
+            "right" => Some(AnimationState::Right),
+            "hurt" => Some(AnimationState::Hurt),
+            "dead" => Some(AnimationState::Dead),
+            _ => None,
+        }
+    }
+
+    pub fn facing(direction : DirectionEnum) -> Self {
+        match direction {
+            DirectionEnum::Up => AnimationState::Up,
+            DirectionEnum::Down => AnimationState::Down,
+            DirectionEnum::Left => AnimationState::Left,
+            DirectionEnum::Right => AnimationState::Right,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Deserialize)]
+pub enum AnimationMode {
+    Loop, // Starts over after the last frame
+    Once, // Sticks on the last frame, for things like dying
+}
+
+// One state's frames, as indexes into the sprite sheet. Every frame lasts 1 / fps seconds,
+// unless durations says otherwise (which is what .aseprite files give us).
+#[derive(Clone, Deserialize)]
+pub struct Sequence {
+    frames : Vec<u32>,
+    #[serde(default)]
+    fps : f32,
+    #[serde(default)]
+    durations : Vec<f32>,
+    mode : AnimationMode,
+}
+
+impl Sequence {
+    pub fn timed(frames : Vec<u32>, durations : Vec<f32>, mode : AnimationMode) -> Self {
+        Sequence {
+            frames,
+            fps : 0.0,
+            durations,
+            mode,
+        }
+    }
+
+    // How long a frame stays up, None if it stays up forever
+    fn duration(&self, frame : usize) -> Option<f32> {
+        match self.durations.get(frame) {
+            Some(&duration) if duration > 0.0 => Some(duration),
+            Some(_) => None,
+            None if self.fps > 0.0 => Some(1.0 / self.fps),
+            None => None,
+        }
+    }
+}
+
+// Every state an entity can be in, plus where it's at in the current one
+pub struct Animation {
+    sequences : HashMap<AnimationState, Sequence>,
+    state : AnimationState,
+    frame : usize,
+    time : f32, // Seconds spent on the current frame
+}
+
+impl Animation {
+    pub fn new(sequences : HashMap<AnimationState, Sequence>, state : AnimationState) -> Self {
+        Animation {
+            sequences,
+            state,
+            frame : 0,
+            time : 0.0,
+        }
+    }
+
+    // The sheet index to start on, before animate() has had a chance to run
+    pub fn first_frame(&self) -> u32 {
+        self.sequences.get(&self.state)
+            .and_then(|sequence| sequence.frames.first().copied())
+            .unwrap_or(0)
+    }
+}
+
+// Picks each animated entity's state and moves it along to the right frame
+pub fn animate(
+    time : Res<Time>,
+    mut query : Query<(&mut Animation, &mut TextureAtlasSprite, Option<&Direction>, Option<&Health>, Option<&Hurt>)>,
+) {
+    for (mut animation, mut sprite, direction, health, hurt) in query.iter_mut() {
+        let animation = &mut *animation;
+        // Dead beats hurt, hurt beats whichever way it's facing
+        let wanted = if health.is_some_and(|health| health.hp <= 0) {
+            AnimationState::Dead
+        } else if hurt.is_some() {
+            AnimationState::Hurt
+        } else {
+            direction.map_or(animation.state, |direction| AnimationState::facing(direction.direction))
+        };
+        // Anything without frames for a state just carries on with what it's doing
+        if wanted != animation.state && animation.sequences.contains_key(&wanted) {
+            animation.state = wanted;
+            animation.frame = 0;
+            animation.time = 0.0;
+        } else {
+            animation.time += time.delta_seconds();
+        }
+
+        let sequence = match animation.sequences.get(&animation.state) {
+            Some(sequence) if !sequence.frames.is_empty() => sequence,
+            _ => continue,
+        };
+        let last = sequence.frames.len() - 1;
+        while let Some(frame_time) = sequence.duration(animation.frame) {
+            // Once sequences stop on their last frame, however long it's meant to last
+            if animation.time < frame_time || (animation.frame == last && matches!(sequence.mode, AnimationMode::Once)) {
+                break;
+            }
+            animation.time -= frame_time;
+            animation.frame = if animation.frame == last { 0 } else { animation.frame + 1 };
+        }
+        sprite.index = sequence.frames[animation.frame.min(last)];
+    }
+}
@@ -0,0 +1,379 @@
+/*
+
+Loads .aseprite files straight out of assets/, so there's no exporting PNGs by hand anymore.
+Every frame (all visible layers flattened together) goes into one texture atlas, and every tag
+becomes a clip with that frame range and each frame's own duration. Tags named after an
+AnimationState (up, down, hurt, dead, ...) are what the animation system picks up.
+
+The .aseprite files in assets/ are the only copy of the art, so that's where to edit it (boolet.png
+is the one thing still exported by hand, from boolet.aseprite). Only RGBA files are supported,
+which is what every file in assets/ is.
+File format: https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+*/
+
+use std::{collections::HashMap, io::Read};
+
+use anyhow::{bail, ensure, Context};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    render::texture::{Extent3d, TextureDimension, TextureFormat},
+    utils::BoxedFuture,
+};
+use flate2::read::ZlibDecoder;
+
+use super::animation::{AnimationMode, AnimationState, Sequence};
+
+// One tag's worth of frames, as atlas indexes with how long each one stays up (in seconds)
+#[derive(Clone)]
+pub struct Clip {
+    pub frames : Vec<u32>,
+    pub durations : Vec<f32>,
+    pub mode : AnimationMode,
+}
+
+#[derive(TypeUuid)]
+#[uuid = "5b1e4f8a-2d7c-4c3e-9a61-7f0d2b8e4c19"]
+pub struct Aseprite {
+    pub atlas : Handle<TextureAtlas>,
+    pub size : Vec2, // Size of one frame, in pixels
+    pub clips : HashMap<String, Clip>,
+}
+
+impl Aseprite {
+    // The clips whose tag names match an AnimationState, ready to hand to an Animation
+    pub fn sequences(&self) -> HashMap<AnimationState, Sequence> {
+        self.clips.iter()
+            .filter_map(|(name, clip)| {
+                let state = AnimationState::from_name(name)?;
+                Some((state, Sequence::timed(clip.frames.clone(), clip.durations.clone(), clip.mode)))
+            })
+            .collect()
+    }
+}
+
+// Little-endian reader over the file, everything in the format is one of these
+struct Reader<'a> {
+    bytes : &'a [u8],
+    position : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count : usize) -> anyhow::Result<&'a [u8]> {
+        ensure!(self.position + count <= self.bytes.len(), "unexpected end of file");
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, count : usize) -> anyhow::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    // Jump straight to an offset from the start of the file
+    fn seek(&mut self, position : usize) -> anyhow::Result<()> {
+        ensure!(position <= self.bytes.len(), "unexpected end of file");
+        self.position = position;
+        Ok(())
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn short(&mut self) -> anyhow::Result<i16> {
+        Ok(self.word()? as i16)
+    }
+
+    fn dword(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let length = self.word()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+}
+
+struct Layer {
+    visible : bool,
+    normal : bool, // Group and tilemap layers don't have pixels of their own
+    opacity : u8,
+}
+
+// A layer's pixels on one frame, positioned on the canvas
+#[derive(Clone)]
+struct Cel {
+    x : i32,
+    y : i32,
+    width : usize,
+    height : usize,
+    opacity : u8,
+    pixels : Vec<u8>, // RGBA
+}
+
+struct Tag {
+    name : String,
+    from : usize,
+    to : usize,
+    repeat : u16, // 0 means forever, anything else is how many times it plays
+}
+
+const HEADER_SIZE : usize = 128; // The first frame starts right after
+const HEADER_MAGIC : u16 = 0xA5E0;
+const FRAME_MAGIC : u16 = 0xF1FA;
+const LAYER_CHUNK : u16 = 0x2004;
+const CEL_CHUNK : u16 = 0x2005;
+const TAGS_CHUNK : u16 = 0x2018;
+
+// Everything we need out of a file: canvas size, every frame flattened to RGBA, frame durations and tags
+struct Parsed {
+    width : usize,
+    height : usize,
+    frames : Vec<Vec<u8>>,
+    durations : Vec<f32>,
+    tags : Vec<Tag>,
+}
+
+fn parse(bytes : &[u8]) -> anyhow::Result<Parsed> {
+    let mut reader = Reader { bytes, position : 0 };
+    reader.skip(4)?; // File size
+    ensure!(reader.word()? == HEADER_MAGIC, "not an aseprite file");
+    let frame_count = reader.word()? as usize;
+    let width = reader.word()? as usize;
+    let height = reader.word()? as usize;
+    let depth = reader.word()?;
+    ensure!(depth == 32, "only RGBA sprites are supported, this one is {} bits per pixel", depth);
+    // Layer opacity only counts if this is set, really old files leave it garbage
+    let layer_opacity = reader.dword()? & 1 != 0;
+    reader.seek(HEADER_SIZE)?; // Skip the rest of the header
+
+    let mut layers = Vec::new();
+    let mut tags = Vec::new();
+    let mut durations = Vec::new();
+    // cels[frame][layer]
+    let mut cels : Vec<Vec<Option<Cel>>> = Vec::new();
+    for frame in 0..frame_count {
+        let frame_start = reader.position;
+        let frame_size = reader.dword()? as usize;
+        ensure!(reader.word()? == FRAME_MAGIC, "frame {} is corrupt", frame);
+        let old_chunks = reader.word()? as usize;
+        durations.push(reader.word()? as f32 / 1000.0);
+        reader.skip(2)?;
+        let new_chunks = reader.dword()? as usize;
+        let chunks = if new_chunks == 0 { old_chunks } else { new_chunks };
+        cels.push(Vec::new());
+
+        for _ in 0..chunks {
+            let chunk_start = reader.position;
+            let chunk_size = reader.dword()? as usize;
+            let chunk_type = reader.word()?;
+            match chunk_type {
+                LAYER_CHUNK => {
+                    let flags = reader.word()?;
+                    let layer_type = reader.word()?;
+                    reader.skip(2 + 4)?; // Child level, default size
+                    let blend_mode = reader.word()?;
+                    let opacity = reader.byte()?;
+                    if blend_mode != 0 {
+                        warn!("Aseprite blend modes aren't supported, drawing layer {} as normal", layers.len());
+                    }
+                    layers.push(Layer {
+                        visible : flags & 1 != 0,
+                        normal : layer_type == 0,
+                        opacity : if layer_opacity { opacity } else { 255 },
+                    });
+                },
+                CEL_CHUNK => {
+                    let layer = reader.word()? as usize;
+                    let x = reader.short()? as i32;
+                    let y = reader.short()? as i32;
+                    let opacity = reader.byte()?;
+                    let cel_type = reader.word()?;
+                    reader.skip(7)?; // z-index and reserved
+                    let cel = match cel_type {
+                        // Raw or zlib compressed pixels
+                        0 | 2 => {
+                            let cel_width = reader.word()? as usize;
+                            let cel_height = reader.word()? as usize;
+                            let remaining = (chunk_start + chunk_size).checked_sub(reader.position).context("cel chunk is too short")?;
+                            let data = reader.take(remaining)?;
+                            let pixels = if cel_type == 0 {
+                                data.to_vec()
+                            } else {
+                                let mut pixels = Vec::with_capacity(cel_width * cel_height * 4);
+                                ZlibDecoder::new(data).read_to_end(&mut pixels).context("cel pixels are corrupt")?;
+                                pixels
+                            };
+                            ensure!(pixels.len() >= cel_width * cel_height * 4, "cel on frame {} is too small", frame);
+                            Some(Cel { x, y, width : cel_width, height : cel_height, opacity, pixels })
+                        },
+                        // Linked, same pixels as this layer on an earlier frame
+                        1 => {
+                            let linked = reader.word()? as usize;
+                            cels.get(linked).and_then(|cels| cels.get(layer)).cloned().flatten()
+                        },
+                        // Tilemaps
+                        _ => None,
+                    };
+                    let frame_cels = cels.last_mut().unwrap();
+                    if frame_cels.len() <= layer {
+                        frame_cels.resize(layer + 1, None);
+                    }
+                    frame_cels[layer] = cel;
+                },
+                TAGS_CHUNK => {
+                    let count = reader.word()?;
+                    reader.skip(8)?;
+                    for _ in 0..count {
+                        let from = reader.word()? as usize;
+                        let to = reader.word()? as usize;
+                        reader.skip(1)?; // Direction, everything plays forward
+                        let repeat = reader.word()?;
+                        reader.skip(6 + 3 + 1)?; // Reserved, colour
+                        let name = reader.string()?;
+                        if to >= frame_count || from > to {
+                            bail!("tag {} covers frames that don't exist", name);
+                        }
+                        tags.push(Tag { name, from, to, repeat });
+                    }
+                },
+                // Palettes, user data, slices and so on, none of which matter here
+                _ => {},
+            }
+            reader.position = chunk_start + chunk_size;
+        }
+        reader.position = frame_start + frame_size;
+    }
+
+    let frames = cels.iter()
+        .map(|frame_cels| flatten(width, height, &layers, frame_cels))
+        .collect();
+    Ok(Parsed { width, height, frames, durations, tags })
+}
+
+// Draws every visible layer's cel onto a blank canvas, bottom layer first
+fn flatten(width : usize, height : usize, layers : &[Layer], cels : &[Option<Cel>]) -> Vec<u8> {
+    let mut canvas = vec![0u8; width * height * 4];
+    for (layer, cel) in layers.iter().zip(cels.iter()) {
+        let cel = match cel {
+            Some(cel) if layer.visible && layer.normal => cel,
+            _ => continue,
+        };
+        let opacity = cel.opacity as f32 / 255.0 * layer.opacity as f32 / 255.0;
+        for cel_y in 0..cel.height {
+            for cel_x in 0..cel.width {
+                let x = cel.x + cel_x as i32;
+                let y = cel.y + cel_y as i32;
+                if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                    continue;
+                }
+                let source = &cel.pixels[(cel_y * cel.width + cel_x) * 4..][..4];
+                let target = &mut canvas[(y as usize * width + x as usize) * 4..][..4];
+                // Plain "over" blending
+                let alpha = source[3] as f32 / 255.0 * opacity;
+                let below = target[3] as f32 / 255.0;
+                let out = alpha + below * (1.0 - alpha);
+                if out <= 0.0 {
+                    continue;
+                }
+                for channel in 0..3 {
+                    let mixed = (source[channel] as f32 * alpha + target[channel] as f32 * below * (1.0 - alpha)) / out;
+                    target[channel] = mixed.round() as u8;
+                }
+                target[3] = (out * 255.0).round() as u8;
+            }
+        }
+    }
+    canvas
+}
+
+#[derive(Default)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes : &'a [u8],
+        load_context : &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let parsed = parse(bytes).with_context(|| format!("couldn't read {}", load_context.path().display()))?;
+            // Every frame side by side in one strip, which from_grid then cuts back up
+            let frame_count = parsed.frames.len();
+            let strip_width = parsed.width * frame_count;
+            let mut strip = vec![0u8; strip_width * parsed.height * 4];
+            for (index, frame) in parsed.frames.iter().enumerate() {
+                for y in 0..parsed.height {
+                    let row = &frame[y * parsed.width * 4..][..parsed.width * 4];
+                    strip[(y * strip_width + index * parsed.width) * 4..][..parsed.width * 4].copy_from_slice(row);
+                }
+            }
+            let texture = load_context.set_labeled_asset("texture", LoadedAsset::new(Texture::new(
+                Extent3d::new(strip_width as u32, parsed.height as u32, 1),
+                TextureDimension::D2,
+                strip,
+                TextureFormat::Rgba8UnormSrgb,
+            )));
+            let size = Vec2::new(parsed.width as f32, parsed.height as f32);
+            let atlas = load_context.set_labeled_asset(
+                "atlas",
+                LoadedAsset::new(TextureAtlas::from_grid(texture, size, frame_count, 1)),
+            );
+
+            let clip = |from : usize, to : usize, mode| Clip {
+                frames : (from as u32..=to as u32).collect(),
+                durations : parsed.durations[from..=to].to_vec(),
+                mode,
+            };
+            let mut clips = HashMap::new();
+            for tag in parsed.tags.iter() {
+                let mode = if tag.repeat == 0 { AnimationMode::Loop } else { AnimationMode::Once };
+                clips.insert(tag.name.clone(), clip(tag.from, tag.to, mode));
+            }
+            // Untagged files still get something to play
+            if clips.is_empty() && frame_count > 0 {
+                clips.insert("default".to_string(), clip(0, frame_count - 1, AnimationMode::Loop));
+            }
+
+            load_context.set_default_asset(LoadedAsset::new(Aseprite { atlas, size, clips }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+pub struct AsepritePlugin;
+
+impl Plugin for AsepritePlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .add_asset::<Aseprite>()
+            .init_asset_loader::<AsepriteLoader>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_player_sprite() {
+        let parsed = parse(include_bytes!("../assets/player/player.aseprite")).unwrap();
+        assert_eq!((parsed.width, parsed.height), (16, 16));
+        assert_eq!(parsed.frames.len(), 6);
+        assert_eq!(parsed.durations.len(), 6);
+        assert!(parsed.frames.iter().all(|frame| frame.len() == 16 * 16 * 4));
+    }
+}
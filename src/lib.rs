@@ -0,0 +1,1828 @@
+/*
+
+All things with sprites have an up, down, left, right, hurt, dead, etc
+animation to simplify changing sprites, see animation.rs
+
+The whole game is a library so it can run without a window. main.rs opens one and adds
+GamePlugins and Menu, the tests in tests/ add GamePlugins to MinimalPlugins instead.
+
+*/
+
+// Systems take whatever they need as arguments and queries get long, that's just how bevy is
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::{
+    prelude::*,
+    app::{AppExit, PluginGroupBuilder},
+    ecs::component::Component,
+};
+use serde::Deserialize;
+
+mod actions;
+use actions::{ActionPlugin, Gamepads};
+pub use actions::{Action, Bindings};
+mod animation;
+use animation::animate;
+mod aseprite;
+use aseprite::AsepritePlugin;
+mod prefab;
+use prefab::{PrefabPlugin, SpawnPrefabExt};
+pub use prefab::{Prefab, Prefabs};
+mod replay;
+use replay::{ReplayPlugin, TickInput};
+pub use replay::{FixedSeed, Recording, Replay, RunSeed, SaveReplay};
+mod rng;
+use rng::Rng;
+mod terrain;
+use terrain::{Cave, TerrainPlugin};
+pub use terrain::Terrain;
+mod timestep;
+use timestep::{FixedUpdate, Interpolated, TimestepPlugin};
+pub use timestep::{FixedTime, STEP};
+
+ // Weird things
+// Used to tell Bevy what mode we're in, allowing us to switch between gameplay and menus
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum AppState {
+    Menu,         // Used for the titlescreen
+    StartGame,   // Used once when transitioning from Menu to the Game this ensures that unpausing does fuck up the game
+    InGame,     // Used to run the game loop, includes Player movement and Enemy AI
+    Paused,    // Simply a pause screen
+    Options,  // Key rebinding, reached from the title screen
+    GameOver, // I think you're not stupid.
+}
+
+// Used for general collision
+#[derive(Copy, Clone, Deserialize)]
+enum Collider {
+    Player,         // Assigned only to the player
+    Enemy,         // Assigned to enemies the player can collide with
+    Bullet,       // Assigned to bullets, used in conjunction with Faction to determine what they should hit.
+    Environment, // Assigned to the environment. Cave walls and rocks, see terrain.rs
+    Pickup,     // Assigned to things the player can grab, like weapon upgrades
+}
+
+// Used to tell things with no inherit Player/Enemy alliance what to not hit.
+#[derive(Copy, Clone, Deserialize)]
+pub enum FactionEnum {
+    Players,
+    Enemies,
+}
+/* These two both have Enum in their name 
+      to differentiate them from their 
+            associated Components          */
+#[derive(Copy, Clone, Deserialize)]
+enum DirectionEnum { // Self explanitory
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl DirectionEnum {
+    // Whichever axis a vector mostly points along
+    fn from_vec(vector : Vec2) -> Self {
+        if vector.x.abs() > vector.y.abs() {
+            if vector.x > 0.0 { DirectionEnum::Right } else { DirectionEnum::Left }
+        } else if vector.y > 0.0 {
+            DirectionEnum::Up
+        } else {
+            DirectionEnum::Down
+        }
+    }
+
+    fn to_vec(self) -> Vec2 {
+        match self {
+            DirectionEnum::Up => Vec2::new(0.0, 1.0),
+            DirectionEnum::Down => Vec2::new(0.0, -1.0),
+            DirectionEnum::Left => Vec2::new(-1.0, 0.0),
+            DirectionEnum::Right => Vec2::new(1.0, 0.0),
+        }
+    }
+}
+
+// Everything a menu button can do
+#[derive(Copy, Clone, PartialEq)]
+enum ButtonAction {
+    Start,
+    Options,
+    Quit,
+    Resume,
+    Restart,
+    ToMenu,
+    Rebind(Action), // Waits for the next key press and binds it to the action
+    ToggleTwinStick,
+    ResetBindings,
+}
+
+#[derive(Copy, Clone, PartialEq, Deserialize)]
+pub enum EnemyAI {
+    Chaser,
+    Gunner,
+}
+
+// Entities
+pub struct Player;
+pub struct Enemy;
+pub struct Bullet;
+struct Pickup; // Weapon upgrades dropped by dead enemies or left lying around the cave
+
+// Components
+struct Controllable;        // Entities that can be moved with the movement function
+pub struct Health { pub hp : i16, pub max_hp : i16 } // Health, this is the quintessential ECS Component, the obvious one.
+struct Speed { speed : f32 }    // Speed, determines how quickly moving entities can move
+struct Damage { damage : i16 } // Damage determines how much Health you reduce when attacking
+struct Points { points : u32 } // Added to the Score when this dies
+// Invulnerability frames. After getting hurt nothing can damage this for `duration` seconds, time is what's left of that.
+struct IFrames {
+    duration : f32,
+    time : f32,
+}
+// Given to things that just got hurt, they show their hurt frame and flash until time runs out
+struct Hurt { time : f32 }
+// A shove from getting hit, in pixels per second. Dies off quickly.
+struct Knockback { velocity : Vec2 }
+struct Direction { direction : DirectionEnum }
+struct Velocity { velocity : Vec2 } // In pixels per second, for things that fly in any direction (bullets)
+// For things that can't turn on a dime. heading is in radians (0 = right, like atan2), turn_rate in radians per second.
+struct Steering {
+    heading : f32,
+    turn_rate : f32,
+}
+pub struct Faction { pub faction : FactionEnum }
+struct Dead; // Given to things once their Health runs out, so they only get to die once
+// The bullet prefab carries everything about the projectile (look, damage, speed, lifetime, faction),
+// so swapping it out is all it takes to give something a different gun.
+struct Shooter {
+    bullet : Handle<Prefab>,
+    muzzle : f32, // How far in front of the shooter bullets appear
+    shots : u32, // Bullets per shot, fanned out evenly across spread
+    spread : f32, // Radians between the outermost bullets of a shot
+    rate : f32, // Fire rate multiplier, 2.0 shoots twice as often as max_time_out says
+    pierce : bool, // Bullets keep going after they hit something
+    homing : bool, // Bullets curve toward the nearest enemy
+    time_out : f32,
+    max_time_out : f32,
+}
+
+impl Shooter {
+    // Seconds between shots once rate is taken into account
+    fn cooldown(&self) -> f32 {
+        self.max_time_out / self.rate
+    }
+}
+
+// Which WEAPON_TIERS entry the player's gun is on. Pickups bump it up, getting hurt knocks it back down one.
+struct Weapon { tier : usize }
+
+// One step of the weapon upgrade ladder, each tier keeps everything the one before it had
+struct WeaponTier {
+    name : &'static str,
+    shots : u32,
+    spread : f32,
+    rate : f32,
+    pierce : bool,
+    homing : bool,
+}
+
+const WEAPON_TIERS : [WeaponTier; 5] = [
+    WeaponTier { name : "Pea Shooter", shots : 1, spread : 0.0, rate : 1.0, pierce : false, homing : false },
+    WeaponTier { name : "Spread Shot", shots : 3, spread : 0.4, rate : 1.0, pierce : false, homing : false },
+    WeaponTier { name : "Rapid Fire", shots : 3, spread : 0.4, rate : 1.6, pierce : false, homing : false },
+    WeaponTier { name : "Piercing", shots : 3, spread : 0.4, rate : 1.6, pierce : true, homing : false },
+    WeaponTier { name : "Homing", shots : 3, spread : 0.4, rate : 1.6, pierce : true, homing : true },
+];
+
+// Every this many kills an enemy drops a weapon pickup
+const PICKUP_EVERY : u32 = 8;
+
+// Bullets that don't stop at the first thing they hit. Remembers who it's already hit so it only hurts them once.
+struct Piercing { hit : Vec<Entity> }
+// Bullets that turn toward the nearest enemy, turn_rate in radians per second like Steering
+struct Homing { turn_rate : f32 }
+
+struct Expire { time : f32 } // time in seconds to wait before despawning the associated entity
+// Lets an entity hurt whatever it bumps into (using its Damage), but only once every max_time_out seconds
+struct ContactDamage {
+    time_out : f32,
+    max_time_out : f32,
+}
+/* Give this to an Entity and fill it with another entity (with the Direction
+ component) to spawn it whenever an entity "shoots" something */
+// used for camera scrolling, to differentiate from other entities with Transform.
+struct Scrolling;
+// How big something is as far as collisions go, separate from how it's drawn (sprite sheets get scaled up)
+pub struct Hitbox { pub size : Vec2 }
+
+// Scoping markers. Every spawned entity carries the one for the AppState that owns it,
+// so each clean_* system knows exactly what it's allowed to despawn.
+struct GameScoped;      // Player, enemies, bullets, pickups, the HUD, the game's cameras
+struct PauseScoped;    // The pause overlay
+struct MenuScoped;    // The title screen
+struct OptionsScoped; // The Options screen
+struct GameOverScoped; // The Game Over screen
+
+// UI
+// HUD pieces, each one only gets touched when whatever it shows changes
+struct HealthBar;  // The coloured fill of the health bar, its width is the player's remaining health
+struct DepthText;
+struct ScoreText;
+struct WeaponText; // The HUD line showing the player's weapon tier
+struct BindingLabel(Action); // Text on an Options button, showing what the action is bound to
+struct TwinStickLabel; // Text on the Options button that turns twin stick aiming on and off
+// Buttons are numbered top to bottom so the keyboard can walk through them
+struct MenuButton {
+    action : ButtonAction,
+    index : usize,
+}
+
+// Resources
+// What the current run has achieved so far, shown on the Game Over screen
+#[derive(Default)]
+struct RunStats {
+    height : f32,  // How far the camera has climbed, in pixels
+    kills : u32,
+    time : f32,  // Seconds spent actually playing (pausing doesn't count)
+}
+
+impl RunStats {
+    // Every 10 pixels climbed is a metre closer to the surface
+    fn climbed(&self) -> f32 {
+        self.height / 10.0
+    }
+
+    fn depth(&self) -> f32 {
+        depth_at(self.height)
+    }
+}
+
+// The dive starts 1000m down. Takes the camera's height in pixels, same as RunStats::height
+fn depth_at(height : f32) -> f32 {
+    (1000.0 - height / 10.0).max(0.0)
+}
+
+// Points from kills. Kept apart from RunStats since that changes every frame and the HUD only wants to hear about kills.
+#[derive(Default)]
+struct Score(u32);
+
+// Events
+// Sent whenever something takes damage, after its Health has already gone down
+struct DamageEvent {
+    target : Entity,
+    knockback : Vec2, // Which way (and how hard) to shove it
+}
+
+// How long things show their hurt frame, unless their IFrames last longer
+const HURT_TIME : f32 = 0.25;
+
+// How a wave's enemies are arranged when they show up
+#[derive(Copy, Clone)]
+pub enum Formation {
+    Line,    // A row across the top of the screen
+    Column, // Single file down the middle
+    V,     // Arrowhead pointing at the player
+    Flanks, // Alternating down both sides
+}
+
+// One entry of the wave table. `at` is how many metres the player has to climb before it spawns.
+pub struct Wave {
+    pub at : f32,
+    pub kind : EnemyAI,
+    pub count : u32,
+    pub formation : Formation,
+}
+
+/* Every wave in order. Once the player climbs past `length` metres the table starts over,
+   and since difficulty keeps growing with the distance climbed each loop hits harder than the last.
+   Swap this resource out to change how a run plays. */
+pub struct WaveTable {
+    pub waves : Vec<Wave>,
+    pub length : f32,
+}
+
+impl Default for WaveTable {
+    fn default() -> Self {
+        let wave = |at, kind, count, formation| Wave { at, kind, count, formation };
+        WaveTable {
+            waves : vec![
+                wave(0.0, EnemyAI::Chaser, 1, Formation::Line),
+                wave(15.0, EnemyAI::Chaser, 3, Formation::Line),
+                wave(35.0, EnemyAI::Gunner, 1, Formation::Column),
+                wave(50.0, EnemyAI::Chaser, 5, Formation::V),
+                wave(70.0, EnemyAI::Gunner, 2, Formation::Flanks),
+                wave(90.0, EnemyAI::Chaser, 4, Formation::Flanks),
+                wave(110.0, EnemyAI::Gunner, 3, Formation::Line),
+                wave(130.0, EnemyAI::Chaser, 6, Formation::Column),
+            ],
+            length : 150.0,
+        }
+    }
+}
+
+// How far into the WaveTable the current run is, and where in the cave the next waves turn up
+#[derive(Default)]
+struct WaveSpawner {
+    next : usize,
+    loops : u32,
+    rng : Rng,
+}
+// The wave spawner's Rng::stream, terrain.rs has the rest
+const WAVE_STREAM : u64 = 4 << 32;
+// How far new enemies stay from the cave walls, about half an enemy
+const SPAWN_MARGIN : f32 = 24.0;
+
+// Whatever comes after flag on the command line, like the file in --replay last.replay
+fn argument(flag : &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    args.next()
+}
+
+// Index of the MenuButton currently picked with the keyboard (or last hovered with the mouse)
+#[derive(Default)]
+struct MenuSelection(usize);
+// The action the Options screen is waiting on a new key for, if any
+#[derive(Default)]
+struct Rebinding(Option<Action>);
+// Font and colours shared by every menu
+struct UiAssets {
+    font : Handle<Font>,
+    clear : Handle<ColorMaterial>,
+    dim : Handle<ColorMaterial>,
+    normal : Handle<ColorMaterial>,
+    hovered : Handle<ColorMaterial>,
+    pressed : Handle<ColorMaterial>,
+    health : Handle<ColorMaterial>,
+}
+
+impl FromWorld for UiAssets {
+    fn from_world(world : &mut World) -> Self {
+        let font = world.get_resource::<AssetServer>().unwrap().load("fonts/DejaVuSans-Bold.ttf");
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        UiAssets {
+            font,
+            clear : materials.add(Color::NONE.into()),
+            dim : materials.add(Color::rgba(0.0, 0.0, 0.0, 0.6).into()),
+            normal : materials.add(Color::rgb_u8(9, 54, 99).into()),
+            hovered : materials.add(Color::rgb_u8(24, 98, 156).into()),
+            pressed : materials.add(Color::rgb_u8(64, 160, 200).into()),
+            health : materials.add(Color::rgb_u8(200, 40, 50).into()),
+        }
+    }
+}
+
+ // God help you
+// I mean, Systems.
+fn setup_game(
+    mut state : ResMut<State<AppState>>,
+    mut commands : Commands,
+    prefabs : Res<Prefabs>,
+    mut stats : ResMut<RunStats>,
+    mut score : ResMut<Score>,
+    mut spawner : ResMut<WaveSpawner>,
+    seed : Res<RunSeed>,
+) {
+    // Fresh run, fresh stats, and start the waves from the top
+    *stats = RunStats::default();
+    *score = Score::default();
+    *spawner = WaveSpawner {
+        rng : Rng::stream(seed.0, WAVE_STREAM),
+        ..Default::default()
+    };
+    // Spawn the camera and give it the scrolling component so it moves up slowly
+    commands.spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(Scrolling)
+        .insert(Interpolated::default())
+        .insert(GameScoped);
+    // UI camera
+    commands.spawn_bundle(UiCameraBundle::default())
+        .insert(GameScoped);
+    // Spawn the player. Health, speed, sprites and their gun all come from assets/prefabs/player.prefab
+    commands.spawn_prefab(prefabs.player.clone(), Transform::from_xyz(1.0, 1.0, 0.0))
+        .insert(Weapon { tier : 0 })
+        .insert(GameScoped);
+
+    // After the Game is prepared switch to in game mode. This runs the actual game loop.
+    state.set(AppState::InGame).unwrap();
+}
+
+// One line of HUD text, white and a bit smaller than the menus'
+fn hud_text(ui : &UiAssets, text : String) -> TextBundle {
+    TextBundle {
+        style : Style {
+            margin : Rect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        text : Text::with_section(
+            text,
+            TextStyle {
+                font : ui.font.clone(),
+                font_size : 22.0,
+                color : Color::rgb(0.9, 0.95, 1.0),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    }
+}
+
+// In-game overlay in the top left corner, drawn by the UI camera setup_game spawns.
+// Everything starts out blank, the update_* systems fill it in the first frame since it all counts as changed then.
+fn setup_hud(mut commands : Commands, ui : Res<UiAssets>) {
+    commands.spawn_bundle(NodeBundle {
+        style : Style {
+            position_type : PositionType::Absolute,
+            position : Rect {
+                left : Val::Px(12.0),
+                top : Val::Px(12.0),
+                ..Default::default()
+            },
+            flex_direction : FlexDirection::ColumnReverse,
+            align_items : AlignItems::FlexStart,
+            ..Default::default()
+        },
+        material : ui.clear.clone(),
+        ..Default::default()
+    })
+        .insert(GameScoped)
+        .with_children(|parent| {
+            // Health bar, a dark back with the fill on top
+            parent.spawn_bundle(NodeBundle {
+                style : Style {
+                    size : Size::new(Val::Px(200.0), Val::Px(16.0)),
+                    margin : Rect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                material : ui.dim.clone(),
+                ..Default::default()
+            })
+                .with_children(|parent| {
+                    parent.spawn_bundle(NodeBundle {
+                        style : Style {
+                            size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                            ..Default::default()
+                        },
+                        material : ui.health.clone(),
+                        ..Default::default()
+                    })
+                        .insert(HealthBar);
+                });
+            parent.spawn_bundle(hud_text(&ui, String::new())).insert(DepthText);
+            parent.spawn_bundle(hud_text(&ui, String::new())).insert(ScoreText);
+            parent.spawn_bundle(hud_text(&ui, String::new())).insert(WeaponText);
+        });
+}
+
+// Despawns everything carrying the scoping marker T, children included (UI nodes have a lot of those)
+fn despawn_scoped<T : Component>(commands : &mut Commands, query : &Query<Entity, With<T>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Remove everything the game spawned so the next run starts from a clean slate
+fn clean_game(
+    mut commands : Commands,
+    query : Query<Entity, With<GameScoped>>,
+) {
+    despawn_scoped(&mut commands, &query);
+}
+
+// Pause overlay. The game's UI camera is still around (Paused is pushed on top of InGame), so we just draw over it.
+fn setup_pause(
+    mut commands : Commands,
+    ui : Res<UiAssets>,
+    mut selection : ResMut<MenuSelection>,
+) {
+    let mut overlay = screen_node(&ui);
+    overlay.material = ui.dim.clone();
+    commands.spawn_bundle(overlay)
+        .insert(PauseScoped)
+        .with_children(|parent| {
+            spawn_text(parent, &ui, "Paused", 48.0);
+            spawn_button(parent, &ui, "Resume", ButtonAction::Resume, 0);
+            spawn_button(parent, &ui, "Restart", ButtonAction::Restart, 1);
+            spawn_button(parent, &ui, "Quit to Menu", ButtonAction::ToMenu, 2);
+        });
+    selection.0 = 0;
+}
+
+fn pause(
+    mut state : ResMut<State<AppState>>,
+    input : Res<Input<Action>>,
+    mut selection : ResMut<MenuSelection>,
+    buttons : Query<(&Interaction, ChangeTrackers<Interaction>, &MenuButton)>,
+) {
+    // Backing out of the pause screen is the same as Resume
+    if input.just_pressed(Action::Back) {
+        state.pop().ok();
+        return;
+    }
+    match navigate_buttons(&input, &mut selection, buttons.iter()) {
+        Some(ButtonAction::Resume) => {
+            state.pop().ok();
+        },
+        // replace() unwinds the whole stack, so clean_pause and then clean_game both get to run
+        Some(ButtonAction::Restart) => {
+            state.replace(AppState::StartGame).ok();
+        },
+        Some(ButtonAction::ToMenu) => {
+            state.replace(AppState::Menu).ok();
+        },
+        _ => (),
+    }
+}
+
+// Remove the pause screen
+fn clean_pause(
+    mut commands : Commands,
+    query : Query<Entity, With<PauseScoped>>,
+) {
+    despawn_scoped(&mut commands, &query);
+}
+
+ // Sub-section, UI
+// Full screen node that stacks its children top to bottom in the middle of the screen
+fn screen_node(ui : &UiAssets) -> NodeBundle {
+    NodeBundle {
+        style : Style {
+            size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            // Plain Column stacks from the bottom up in bevy, Reverse gets us top to bottom
+            flex_direction : FlexDirection::ColumnReverse,
+            justify_content : JustifyContent::Center,
+            align_items : AlignItems::Center,
+            ..Default::default()
+        },
+        material : ui.clear.clone(),
+        ..Default::default()
+    }
+}
+
+fn spawn_text(parent : &mut ChildBuilder, ui : &UiAssets, text : &str, font_size : f32) {
+    parent.spawn_bundle(TextBundle {
+        style : Style {
+            margin : Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        text : Text::with_section(
+            text,
+            TextStyle {
+                font : ui.font.clone(),
+                font_size,
+                color : Color::rgb(0.9, 0.95, 1.0),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    });
+}
+
+fn spawn_button(parent : &mut ChildBuilder, ui : &UiAssets, label : &str, action : ButtonAction, index : usize) {
+    parent.spawn_bundle(ButtonBundle {
+        style : Style {
+            size : Size::new(Val::Px(220.0), Val::Px(50.0)),
+            margin : Rect::all(Val::Px(8.0)),
+            justify_content : JustifyContent::Center,
+            align_items : AlignItems::Center,
+            ..Default::default()
+        },
+        material : ui.normal.clone(),
+        ..Default::default()
+    })
+        .insert(MenuButton {
+            action,
+            index,
+        })
+        .with_children(|parent| spawn_text(parent, ui, label, 28.0));
+}
+
+/* Shared button handling for every menu: Up/Down walk the selection, hovering with the mouse moves it too,
+   and Z or a click picks a button. Returns the picked button's action, if any. */
+fn navigate_buttons<'a>(
+    input : &Input<Action>,
+    selection : &mut MenuSelection,
+    buttons : impl Iterator<Item = (&'a Interaction, ChangeTrackers<Interaction>, &'a MenuButton)>,
+) -> Option<ButtonAction> {
+    let mut actions = Vec::new();
+    let mut clicked = None;
+    for (interaction, tracker, button) in buttons {
+        actions.push((button.index, button.action));
+        // Only react to the mouse when it actually does something, otherwise a resting cursor would fight the keyboard
+        if !tracker.is_changed() {
+            continue;
+        }
+        match interaction {
+            Interaction::Clicked => {
+                selection.0 = button.index;
+                clicked = Some(button.action);
+            },
+            Interaction::Hovered => selection.0 = button.index,
+            Interaction::None => (),
+        }
+    }
+    if clicked.is_some() || actions.is_empty() {
+        return clicked;
+    }
+
+    let count = actions.len();
+    if input.just_pressed(Action::MoveDown) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    if input.just_pressed(Action::MoveUp) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+    if input.just_pressed(Action::Confirm) {
+        return actions.iter()
+            .find(|(index, _)| *index == selection.0)
+            .map(|(_, action)| *action);
+    }
+    None
+}
+
+// Colours buttons by their state, the keyboard selection counts as hovering
+fn button_visuals(
+    ui : Res<UiAssets>,
+    input : Res<Input<Action>>,
+    selection : Res<MenuSelection>,
+    mut query : Query<(&Interaction, &MenuButton, &mut Handle<ColorMaterial>)>,
+) {
+    for (interaction, button, mut material) in query.iter_mut() {
+        let selected = button.index == selection.0;
+        *material = if *interaction == Interaction::Clicked || (selected && input.pressed(Action::Confirm)) {
+            ui.pressed.clone()
+        } else if selected {
+            ui.hovered.clone()
+        } else {
+            ui.normal.clone()
+        };
+    }
+}
+
+// Title screen
+fn setup_menu(
+    mut commands : Commands,
+    ui : Res<UiAssets>,
+    mut selection : ResMut<MenuSelection>,
+) {
+    commands.spawn_bundle(UiCameraBundle::default())
+        .insert(MenuScoped);
+    commands.spawn_bundle(screen_node(&ui))
+        .insert(MenuScoped)
+        .with_children(|parent| {
+            spawn_text(parent, &ui, "Un-Divey", 64.0);
+            spawn_button(parent, &ui, "Start", ButtonAction::Start, 0);
+            spawn_button(parent, &ui, "Options", ButtonAction::Options, 1);
+            spawn_button(parent, &ui, "Quit", ButtonAction::Quit, 2);
+        });
+    selection.0 = 0;
+}
+
+fn menu(
+    mut state : ResMut<State<AppState>>,
+    input : Res<Input<Action>>,
+    asset_server : Res<AssetServer>,
+    prefabs : Res<Prefabs>,
+    prefab_assets : Res<Assets<Prefab>>,
+    mut selection : ResMut<MenuSelection>,
+    mut app_exit : EventWriter<AppExit>,
+    buttons : Query<(&Interaction, ChangeTrackers<Interaction>, &MenuButton)>,
+) {
+    match navigate_buttons(&input, &mut selection, buttons.iter()) {
+        // Prefabs that haven't loaded yet can't be spawned, so hold off until they're ready (it's a few frames at most)
+        Some(ButtonAction::Start) => {
+            if prefabs.loaded(&asset_server, &prefab_assets) {
+                state.set(AppState::StartGame).ok();
+            }
+        },
+        Some(ButtonAction::Options) => {
+            state.set(AppState::Options).ok();
+        },
+        Some(ButtonAction::Quit) => app_exit.send(AppExit),
+        _ => (),
+    }
+}
+
+// Remove the title screen
+fn clean_menu(
+    mut commands : Commands,
+    query : Query<Entity, With<MenuScoped>>,
+) {
+    despawn_scoped(&mut commands, &query);
+}
+
+// What goes on an Options button, like "Fire: Z / South" or "Fire: press a key..." while rebinding
+fn binding_text(bindings : &Bindings, rebinding : &Rebinding, action : Action) -> String {
+    if rebinding.0 == Some(action) {
+        return format!("{}: press a key or button...", action.name());
+    }
+    let keys = bindings.keys(action).iter().map(|key| format!("{:?}", key));
+    let buttons = bindings.buttons(action).iter().map(|button| format!("{:?}", button));
+    let names : Vec<String> = keys.chain(buttons).collect();
+    format!("{}: {}", action.name(), names.join(" / "))
+}
+
+fn twin_stick_text(bindings : &Bindings) -> String {
+    format!("Twin stick aim: {}", if bindings.twin_stick { "On" } else { "Off" })
+}
+
+// Same as spawn_button, just wider and shorter, with a label we can find again to change its text
+fn spawn_option_button<L : Component>(
+    parent : &mut ChildBuilder,
+    ui : &UiAssets,
+    text : &str,
+    action : ButtonAction,
+    index : usize,
+    label : L,
+) {
+    parent.spawn_bundle(ButtonBundle {
+        style : Style {
+            size : Size::new(Val::Px(360.0), Val::Px(28.0)),
+            margin : Rect::all(Val::Px(2.0)),
+            justify_content : JustifyContent::Center,
+            align_items : AlignItems::Center,
+            ..Default::default()
+        },
+        material : ui.normal.clone(),
+        ..Default::default()
+    })
+        .insert(MenuButton {
+            action,
+            index,
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text : Text::with_section(
+                    text,
+                    TextStyle {
+                        font : ui.font.clone(),
+                        font_size : 20.0,
+                        color : Color::rgb(0.9, 0.95, 1.0),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+                .insert(label);
+        });
+}
+
+fn setup_options(
+    mut commands : Commands,
+    ui : Res<UiAssets>,
+    bindings : Res<Bindings>,
+    mut rebinding : ResMut<Rebinding>,
+    mut selection : ResMut<MenuSelection>,
+) {
+    rebinding.0 = None;
+    commands.spawn_bundle(UiCameraBundle::default())
+        .insert(OptionsScoped);
+    commands.spawn_bundle(screen_node(&ui))
+        .insert(OptionsScoped)
+        .with_children(|parent| {
+            spawn_text(parent, &ui, "Controls", 48.0);
+            for (index, &action) in Action::ALL.iter().enumerate() {
+                let text = binding_text(&bindings, &Rebinding(None), action);
+                spawn_option_button(parent, &ui, &text, ButtonAction::Rebind(action), index, BindingLabel(action));
+            }
+            let text = twin_stick_text(&bindings);
+            spawn_option_button(parent, &ui, &text, ButtonAction::ToggleTwinStick, Action::ALL.len(), TwinStickLabel);
+            // Reset and Back side by side, there isn't room for them underneath each other
+            parent.spawn_bundle(NodeBundle {
+                style : Style {
+                    flex_direction : FlexDirection::Row,
+                    ..Default::default()
+                },
+                material : ui.clear.clone(),
+                ..Default::default()
+            })
+                .with_children(|parent| {
+                    spawn_button(parent, &ui, "Reset", ButtonAction::ResetBindings, Action::ALL.len() + 1);
+                    spawn_button(parent, &ui, "Back", ButtonAction::ToMenu, Action::ALL.len() + 2);
+                });
+        });
+    selection.0 = 0;
+}
+
+fn options(
+    mut state : ResMut<State<AppState>>,
+    keys : Res<Input<KeyCode>>,
+    pad_buttons : Res<Input<GamepadButton>>,
+    gamepads : Res<Gamepads>,
+    input : Res<Input<Action>>,
+    mut bindings : ResMut<Bindings>,
+    mut rebinding : ResMut<Rebinding>,
+    mut selection : ResMut<MenuSelection>,
+    buttons : Query<(&Interaction, ChangeTrackers<Interaction>, &MenuButton)>,
+) {
+    // While waiting, the very next key or button pressed gets bound (even if it's already used for something else)
+    if let Some(action) = rebinding.0 {
+        let button = pad_buttons.get_just_pressed().find(|button| gamepads.0.contains(&button.0));
+        if let Some(&key) = keys.get_just_pressed().next() {
+            bindings.rebind(action, key);
+        } else if let Some(&GamepadButton(_, button)) = button {
+            bindings.rebind_button(action, button);
+        } else {
+            return;
+        }
+        bindings.save();
+        rebinding.0 = None;
+        return;
+    }
+    if input.just_pressed(Action::Back) {
+        state.set(AppState::Menu).ok();
+        return;
+    }
+    match navigate_buttons(&input, &mut selection, buttons.iter()) {
+        Some(ButtonAction::Rebind(action)) => rebinding.0 = Some(action),
+        Some(ButtonAction::ToggleTwinStick) => {
+            bindings.twin_stick = !bindings.twin_stick;
+            bindings.save();
+        },
+        Some(ButtonAction::ResetBindings) => {
+            *bindings = Bindings::default();
+            bindings.save();
+        },
+        Some(ButtonAction::ToMenu) => {
+            state.set(AppState::Menu).ok();
+        },
+        _ => (),
+    }
+}
+
+// Keeps the Options labels in step with the bindings, and shows which one is waiting on a key
+fn update_binding_labels(
+    bindings : Res<Bindings>,
+    rebinding : Res<Rebinding>,
+    mut labels : QuerySet<(
+        Query<(&BindingLabel, &mut Text)>,
+        Query<&mut Text, With<TwinStickLabel>>,
+    )>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (label, mut text) in labels.q0_mut().iter_mut() {
+        text.sections[0].value = binding_text(&bindings, &rebinding, label.0);
+    }
+    for mut text in labels.q1_mut().iter_mut() {
+        text.sections[0].value = twin_stick_text(&bindings);
+    }
+}
+
+fn clean_options(
+    mut commands : Commands,
+    query : Query<Entity, With<OptionsScoped>>,
+) {
+    despawn_scoped(&mut commands, &query);
+}
+
+// Movement of Player controlled entities
+fn movement(
+    input : Res<TickInput>, // Make a guess. used for input. Either the controls or a replay, see replay.rs
+    // TIL there's QuerySets in bevy. https://bevy-cheatbook.github.io/cheatsheet.html#query-sets
+    mut set : QuerySet<(
+        // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~ i GUESS everything that can shoot needs Direction, now!
+        Query<(&Controllable, Option<&Health>, &mut Transform, &Speed, &mut Direction)>,  /* Gets Controllable Entities 
+        with optional Health, gets mutable Transform to make changes to position when moving and
+        the Speed component to move at a speed above a blazing fast 1 pixel*/
+        Query<&Transform, With<Scrolling>> /* Gets the Camera (The only thing with the scrolling component),
+            used to make sure the Player is clamped to the bottom of the screen rather than the bottom of 
+            the initial position.*/
+    )>,
+) {
+    let camera_translate = set.q1_mut().single().unwrap().translation; // Only reason to get the camera
+    for (_, health, mut transform, speed, mut facing_direction) in set.q0_mut().iter_mut() {
+        // Shamelessly stolen from an example :p
+        // https://github.com/bevyengine/bevy/blob/cf221f9659127427c99d621b76c8085c4860e2ef/examples/ecs/state.rs
+        let mut direction = Vec3::ZERO;
+        if let Some(health) = health {
+            // Dead things can't move.
+            if health.hp <= 0 {
+                continue;
+            }
+        }
+
+        /* Facing follows the arrows, unless Fire or Aim Lock is held so you can back away from things while
+           shooting them. In twin stick mode the Aim actions turn you even then. */
+        let aim = twin_stick_aim(&input);
+        let locked = input.pressed(Action::Fire) || input.pressed(Action::AimLock);
+        if aim != Vec2::ZERO {
+            facing_direction.direction = DirectionEnum::from_vec(aim);
+        } else if !locked {
+            if input.pressed(Action::MoveLeft) {
+                facing_direction.direction = DirectionEnum::Left;
+            }
+            if input.pressed(Action::MoveRight) {
+                facing_direction.direction = DirectionEnum::Right;
+            }
+            if input.pressed(Action::MoveUp) {
+                facing_direction.direction = DirectionEnum::Up;
+            }
+            if input.pressed(Action::MoveDown) {
+                facing_direction.direction = DirectionEnum::Down;
+            }
+        }
+
+        // Well maybe it's not dead. Or maybe it can't ever BE dead.
+        if input.pressed(Action::MoveLeft) {
+            direction.x -= 1.0;
+        }
+        if input.pressed(Action::MoveRight) {
+            direction.x += 1.0;
+        }
+        // +Y = Up in bevy (for some reason)
+        if input.pressed(Action::MoveUp) {
+            direction.y += 1.0;
+        }
+        // inversely, -Y = Down (shocker, I know!)
+        if input.pressed(Action::MoveDown) {
+            direction.y -= 1.0;
+        }
+
+        // A pushed stick takes over from the digital directions, and moves slower the less it's pushed
+        let velocity = if input.move_stick != Vec2::ZERO {
+            if aim == Vec2::ZERO && !locked {
+                facing_direction.direction = DirectionEnum::from_vec(input.move_stick);
+            }
+            input.move_stick.extend(0.0)
+        } else if direction != Vec3::ZERO {
+            direction.normalize()
+        } else {
+            Vec3::ZERO
+        };
+        if velocity != Vec3::ZERO {
+            transform.translation += velocity * speed.speed * STEP;
+            transform.translation.x = transform.translation.x.clamp(-276.0, 276.0);
+        }
+        // Even if the player isn't moving, clamp the y position properly (you can skip x since the camera never moves left/right)
+        transform.translation.y = transform.translation.y.min(200.0 + camera_translate.y).max(-276.0 + camera_translate.y);
+    }
+}
+
+// Where the Aim actions (or the right stick) point, zero if nothing's held or twin stick mode is off
+fn twin_stick_aim(input : &TickInput) -> Vec2 {
+    if !input.twin_stick {
+        return Vec2::ZERO;
+    }
+    if input.aim_stick != Vec2::ZERO {
+        return input.aim_stick;
+    }
+    let mut aim = Vec2::ZERO;
+    if input.pressed(Action::AimLeft) { aim.x -= 1.0; }
+    if input.pressed(Action::AimRight) { aim.x += 1.0; }
+    if input.pressed(Action::AimUp) { aim.y += 1.0; }
+    if input.pressed(Action::AimDown) { aim.y -= 1.0; }
+    aim
+}
+
+fn controllable_shooting(
+    input : Res<TickInput>,
+    mut commands : Commands,
+    prefab_assets : Res<Assets<Prefab>>,
+    mut query : Query<(&Transform, &Direction, &mut Shooter), (With<Controllable>, Without<Dead>)>,
+) {
+    for (shooter_transform, direction, mut shooter) in query.iter_mut() {
+        // The gun cools off whether or not Fire's held, so a quick tap shoots straight away
+        shooter.time_out -= STEP;
+        if !input.pressed(Action::Fire) {
+            shooter.time_out = shooter.time_out.max(0.0);
+            continue;
+        }
+        if shooter.time_out > 0.0 {
+            continue;
+        }
+        // Whatever's left over carries into the next cooldown, so holding Fire shoots exactly every cooldown() seconds
+        shooter.time_out += shooter.cooldown();
+        // Straight ahead, since holding Fire keeps you facing the same way. Twin stick aim can go diagonal too.
+        let mut aim = twin_stick_aim(&input);
+        if aim == Vec2::ZERO {
+            aim = direction.direction.to_vec();
+        }
+        spawn_bullet(&mut commands, &prefab_assets, &shooter, shooter_transform.translation, aim);
+    }
+}
+
+// Spawns a shooter's bullets flying along `aim` (any length, it gets normalized), starting at the shooter's muzzle.
+// The one path everything with a Shooter fires through, players and enemies alike.
+fn spawn_bullet(
+    commands : &mut Commands,
+    prefab_assets : &Assets<Prefab>,
+    shooter : &Shooter,
+    origin : Vec3,
+    aim : Vec2,
+) {
+    if aim == Vec2::ZERO {
+        return;
+    }
+    let aim = aim.normalize();
+    // The bullet's speed lives in its prefab, so it has to be loaded to know how fast to send it
+    let speed = match prefab_assets.get(&shooter.bullet).and_then(|bullet| bullet.speed) {
+        Some(speed) => speed,
+        None => return,
+    };
+    let start = origin + (aim * shooter.muzzle).extend(0.0);
+    for shot in 0..shooter.shots {
+        // Evenly fan the shots out around aim, a single shot just goes straight down the middle
+        let angle = if shooter.shots > 1 {
+            shooter.spread * (shot as f32 / (shooter.shots - 1) as f32 - 0.5)
+        } else {
+            0.0
+        };
+        let velocity = Vec2::new(
+            aim.x * angle.cos() - aim.y * angle.sin(),
+            aim.x * angle.sin() + aim.y * angle.cos(),
+        ) * speed;
+        let transform = Transform {
+            translation : start,
+            rotation : rotation_from(velocity),
+            ..Default::default()
+        };
+        let mut bullet = commands.spawn_prefab(shooter.bullet.clone(), transform);
+        bullet.insert(Velocity { velocity })
+            .insert(GameScoped);
+        if shooter.pierce {
+            bullet.insert(Piercing { hit : Vec::new() });
+        }
+        if shooter.homing {
+            bullet.insert(Homing { turn_rate : 4.0 });
+        }
+    }
+}
+
+// Bullet sprites are drawn pointing up, so turn them to point along their velocity instead
+fn rotation_from(velocity : Vec2) -> Quat {
+    Quat::from_rotation_z(velocity.y.atan2(velocity.x) - FRAC_PI_2)
+}
+
+// Move all bullets, keeping them pointed the way they're flying
+fn bullet_mover(
+    mut query : Query<(&Velocity, &mut Transform), With<Bullet>>
+) {
+    for (velocity, mut transform) in query.iter_mut() {
+        transform.translation += (velocity.velocity * STEP).extend(0.0);
+        transform.rotation = rotation_from(velocity.velocity);
+    }
+}
+
+// Turn homing bullets toward the nearest living enemy, without changing how fast they're going.
+// Only the player's gun ever gets Homing, so enemies are the only thing worth chasing.
+fn homing(
+    mut bullets : Query<(&Homing, &Transform, &mut Velocity)>,
+    targets : Query<&Transform, (With<Enemy>, Without<Dead>)>,
+) {
+    for (homing, transform, mut velocity) in bullets.iter_mut() {
+        let position = transform.translation.truncate();
+        let nearest = targets.iter()
+            .map(|target| target.translation.truncate() - position)
+            .min_by(|a, b| a.length_squared().partial_cmp(&b.length_squared()).unwrap());
+        let to_target = match nearest {
+            Some(to_target) if to_target != Vec2::ZERO => to_target,
+            _ => continue,
+        };
+        // Same short-way-round turning as the chasers
+        let heading = velocity.velocity.y.atan2(velocity.velocity.x);
+        let wanted = to_target.y.atan2(to_target.x);
+        let turn = (wanted - heading + PI).rem_euclid(TAU) - PI;
+        let max_turn = homing.turn_rate * STEP;
+        let heading = heading + turn.min(max_turn).max(-max_turn);
+        velocity.velocity = Vec2::new(heading.cos(), heading.sin()) * velocity.velocity.length();
+    }
+}
+
+// Remove all expiring entities
+fn expire(
+    mut commands : Commands,
+    mut query : Query<(Entity, &mut Expire)>
+) {
+    for (entity, mut expire) in query.iter_mut() {
+        if STEP > expire.time {
+            commands.entity(entity).despawn();
+        } else {
+            expire.time -= STEP;
+        }
+    }
+}
+
+ // Sub-section, Collision
+// collide() returns None when one box is entirely inside the other (e.g. a bullet buried in an enemy),
+// so for hit detection we only care whether the boxes overlap at all.
+fn overlapping(a_pos : Vec3, a_size : Vec2, b_pos : Vec3, b_size : Vec2) -> bool {
+    let distance = (a_pos.truncate() - b_pos.truncate()).abs();
+    let reach = (a_size + b_size) / 2.0;
+    distance.x < reach.x && distance.y < reach.y
+}
+
+// Bullets hitting things. Faction decides who a bullet is allowed to hit, so the player can't shoot themselves.
+fn bullet_collision(
+    mut commands : Commands,
+    mut damaged : EventWriter<DamageEvent>,
+    mut bullets : Query<(Entity, &Transform, &Hitbox, &Faction, &Damage, &Velocity, Option<&mut Piercing>), With<Bullet>>,
+    mut targets : Query<(Entity, &Transform, &Hitbox, &Collider, &mut Health, Option<&IFrames>)>,
+    terrain : Query<(&Transform, &Hitbox), With<Terrain>>,
+) {
+    for (bullet, bullet_transform, bullet_hitbox, faction, damage, velocity, mut piercing) in bullets.iter_mut() {
+        /* A bullet in a wall has hit the wall, not whatever's poking out the other side. terrain.rs gets rid of it,
+           but that's through Commands, which don't happen until the end of the tick. */
+        let in_wall = terrain.iter().any(|(transform, hitbox)| {
+            overlapping(bullet_transform.translation, bullet_hitbox.size, transform.translation, hitbox.size)
+        });
+        if in_wall {
+            continue;
+        }
+        for (target, transform, hitbox, collider, mut health, iframes) in targets.iter_mut() {
+            // Corpses don't eat bullets, and neither does anything still recovering from the last hit
+            if health.hp <= 0 || iframes.is_some_and(|iframes| iframes.time > 0.0) {
+                continue;
+            }
+            let hittable = matches!(
+                (&faction.faction, collider),
+                (FactionEnum::Players, Collider::Enemy) | (FactionEnum::Enemies, Collider::Player)
+            );
+            if !hittable || !overlapping(bullet_transform.translation, bullet_hitbox.size, transform.translation, hitbox.size) {
+                continue;
+            }
+            // Bullets shove whatever they hit along the way they were flying (if they're flying at all)
+            let knockback = velocity.velocity.normalize_or_zero() * 150.0;
+            // Piercing bullets fly on through, but only get to hurt each thing once
+            if let Some(piercing) = &mut piercing {
+                if !piercing.hit.contains(&target) {
+                    piercing.hit.push(target);
+                    health.hp -= damage.damage;
+                    damaged.send(DamageEvent { target, knockback });
+                }
+                continue;
+            }
+            health.hp -= damage.damage;
+            damaged.send(DamageEvent { target, knockback });
+            // One bullet, one hit.
+            commands.entity(bullet).despawn();
+            break;
+        }
+    }
+}
+
+// Enemies bumping into the player
+fn contact_collision(
+    mut damaged : EventWriter<DamageEvent>,
+    mut set : QuerySet<(
+        Query<(&Transform, &Hitbox, &Damage, &Health, &mut ContactDamage), With<Enemy>>,
+        Query<(Entity, &Transform, &Hitbox, &mut Health, Option<&IFrames>), With<Player>>,
+    )>,
+) {
+    let (player, player_pos, player_size, invulnerable) = match set.q1_mut().single_mut() {
+        Ok((entity, transform, hitbox, _, iframes)) => {
+            (entity, transform.translation, hitbox.size, iframes.is_some_and(|iframes| iframes.time > 0.0))
+        },
+        Err(_) => return,
+    };
+    let mut total_damage = 0;
+    let mut knockback = Vec2::ZERO;
+    for (transform, hitbox, damage, health, mut contact) in set.q0_mut().iter_mut() {
+        contact.time_out = (contact.time_out - STEP).max(0.0);
+        // Dead enemies are harmless, live ones have to wait for their cooldown, and a freshly hurt player can't be hurt again
+        if health.hp <= 0 || contact.time_out > 0.0 || invulnerable {
+            continue;
+        }
+        if overlapping(transform.translation, hitbox.size, player_pos, player_size) {
+            total_damage += damage.damage;
+            contact.time_out = contact.max_time_out;
+            // Bounce the player away from whatever rammed them, unless they're right on top of each other
+            knockback += (player_pos - transform.translation).truncate().normalize_or_zero();
+        }
+    }
+    if total_damage > 0 {
+        if let Ok((_, _, _, mut health, _)) = set.q1_mut().single_mut() {
+            if health.hp > 0 {
+                health.hp -= total_damage;
+                knockback = knockback.normalize_or_zero() * 400.0;
+                damaged.send(DamageEvent {
+                    target : player,
+                    knockback,
+                });
+            }
+        }
+    }
+}
+
+ // Sub-section, Getting hurt
+// Starts the hurt frame, the knockback and (for things that have them) the invulnerability frames
+fn hurt(
+    mut commands : Commands,
+    mut damaged : EventReader<DamageEvent>,
+    mut query : Query<Option<&mut IFrames>>,
+) {
+    for event in damaged.iter() {
+        let mut hurt_time = HURT_TIME;
+        if let Ok(Some(mut iframes)) = query.get_mut(event.target) {
+            iframes.time = iframes.duration;
+            hurt_time = hurt_time.max(iframes.duration);
+        }
+        commands.entity(event.target)
+            .insert(Hurt { time : hurt_time })
+            .insert(Knockback { velocity : event.knockback });
+    }
+}
+
+fn tick_iframes(mut query : Query<&mut IFrames>) {
+    for mut iframes in query.iter_mut() {
+        if iframes.time > 0.0 {
+            iframes.time -= STEP;
+        }
+    }
+}
+
+// Flashes the sprite on and off while Hurt lasts (animate() takes care of showing the hurt frame)
+fn hurt_flash(
+    mut commands : Commands,
+    mut query : Query<(Entity, &mut Hurt, &Health, &mut Visible)>,
+) {
+    for (entity, mut hurt, health, mut visible) in query.iter_mut() {
+        hurt.time -= STEP;
+        // Dying takes over from here, there's a dead animation for that
+        if hurt.time <= 0.0 || health.hp <= 0 {
+            visible.is_visible = true;
+            commands.entity(entity).remove::<Hurt>();
+            continue;
+        }
+        visible.is_visible = (hurt.time * 16.0) as i32 % 2 == 0;
+    }
+}
+
+fn knockback(
+    mut commands : Commands,
+    mut query : Query<(Entity, &mut Knockback, &mut Transform)>,
+) {
+    for (entity, mut knockback, mut transform) in query.iter_mut() {
+        transform.translation += (knockback.velocity * STEP).extend(0.0);
+        knockback.velocity *= (1.0 - 10.0 * STEP).max(0.0);
+        if knockback.velocity.length() < 10.0 {
+            commands.entity(entity).remove::<Knockback>();
+        }
+    }
+}
+
+ // Sub-section, Death
+// Start freshly dead things' corpse timers (their dead animation kicks in by itself)
+fn death(
+    mut commands : Commands,
+    prefabs : Res<Prefabs>,
+    mut stats : ResMut<RunStats>,
+    mut score : ResMut<Score>,
+    query : Query<(Entity, &Health, &Transform, Option<&Player>, Option<&Points>), Without<Dead>>,
+) {
+    for (entity, health, transform, player, points) in query.iter() {
+        if health.hp > 0 {
+            continue;
+        }
+        if player.is_none() {
+            stats.kills += 1;
+            if let Some(points) = points {
+                score.0 += points.points;
+            }
+            if stats.kills.is_multiple_of(PICKUP_EVERY) {
+                commands.spawn_prefab(prefabs.pickup.clone(), Transform::from_translation(transform.translation))
+                    .insert(GameScoped);
+            }
+        }
+        commands.entity(entity)
+            .insert(Dead)
+            // Enemy corpses float around for a second, the player gets a bit longer to take in their failure
+            .insert(Expire {
+                time : if player.is_some() { 2.0 } else { 1.0 }
+            });
+    }
+}
+
+// Grabbing a pickup moves the player's weapon up a tier (or just eats the pickup if it's maxed out)
+fn collect_pickups(
+    mut commands : Commands,
+    pickups : Query<(Entity, &Transform, &Hitbox), With<Pickup>>,
+    mut players : Query<(&Transform, &Hitbox, &mut Weapon), Without<Dead>>,
+) {
+    for (pickup, pickup_transform, pickup_hitbox) in pickups.iter() {
+        for (transform, hitbox, mut weapon) in players.iter_mut() {
+            if !overlapping(pickup_transform.translation, pickup_hitbox.size, transform.translation, hitbox.size) {
+                continue;
+            }
+            weapon.tier = (weapon.tier + 1).min(WEAPON_TIERS.len() - 1);
+            commands.entity(pickup).despawn();
+            break;
+        }
+    }
+}
+
+// Taking any damage costs the player one weapon tier
+fn weapon_damage(mut damaged : EventReader<DamageEvent>, mut query : Query<&mut Weapon>) {
+    for event in damaged.iter() {
+        if let Ok(mut weapon) = query.get_mut(event.target) {
+            weapon.tier = weapon.tier.saturating_sub(1);
+        }
+    }
+}
+
+// Copy the current tier onto the gun whenever it changes
+fn apply_weapon(mut query : Query<(&Weapon, &mut Shooter), Changed<Weapon>>) {
+    for (weapon, mut shooter) in query.iter_mut() {
+        let tier = &WEAPON_TIERS[weapon.tier];
+        shooter.shots = tier.shots;
+        shooter.spread = tier.spread;
+        shooter.rate = tier.rate;
+        shooter.pierce = tier.pierce;
+        shooter.homing = tier.homing;
+    }
+}
+
+fn update_health_bar(
+    players : Query<&Health, (With<Player>, Changed<Health>)>,
+    mut bars : Query<&mut Style, With<HealthBar>>,
+) {
+    for health in players.iter() {
+        let fraction = health.hp.max(0) as f32 / health.max_hp.max(1) as f32;
+        for mut style in bars.iter_mut() {
+            style.size.width = Val::Percent(fraction * 100.0);
+        }
+    }
+}
+
+// The camera moves every frame, but only rewrite the text when the whole number of metres does
+fn update_depth_text(
+    mut shown : Local<Option<u32>>,
+    cameras : Query<&Transform, (With<Scrolling>, Changed<Transform>)>,
+    mut texts : Query<&mut Text, With<DepthText>>,
+) {
+    for transform in cameras.iter() {
+        let depth = depth_at(transform.translation.y) as u32;
+        if *shown == Some(depth) {
+            continue;
+        }
+        *shown = Some(depth);
+        for mut text in texts.iter_mut() {
+            text.sections[0].value = format!("Depth: {}m", depth);
+        }
+    }
+}
+
+fn update_score_text(score : Res<Score>, mut texts : Query<&mut Text, With<ScoreText>>) {
+    if !score.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!("Score: {}", score.0);
+    }
+}
+
+fn update_weapon_text(
+    weapons : Query<&Weapon, Changed<Weapon>>,
+    mut texts : Query<&mut Text, With<WeaponText>>,
+) {
+    for weapon in weapons.iter() {
+        for mut text in texts.iter_mut() {
+            text.sections[0].value = format!("Weapon: {}", WEAPON_TIERS[weapon.tier].name);
+        }
+    }
+}
+
+// Once the player's corpse has expired the run is over
+fn player_death(
+    mut state : ResMut<State<AppState>>,
+    query : Query<Entity, With<Player>>,
+) {
+    if query.iter().next().is_none() {
+        state.set(AppState::GameOver).ok();
+    }
+}
+
+ // Sub-section, Spawning
+// Spawns one enemy of the given kind from its prefab. Difficulty scales its health, 1.0 being the baseline.
+fn spawn_enemy(
+    commands : &mut Commands,
+    prefabs : &Prefabs,
+    prefab_assets : &Assets<Prefab>,
+    kind : EnemyAI,
+    position : Vec3,
+    difficulty : f32,
+) {
+    let prefab = prefabs.enemy(kind);
+    let base_hp = prefab_assets.get(&prefab).and_then(|prefab| prefab.health).unwrap_or(1);
+    commands.spawn_prefab(prefab, Transform::from_translation(position))
+        .insert(Health {
+            hp : (base_hp as f32 * difficulty) as i16,
+            max_hp : (base_hp as f32 * difficulty) as i16,
+        })
+        .insert(GameScoped);
+}
+
+// Where each enemy of a wave starts, relative to the camera. Everything starts just above the top of the screen.
+fn formation_offsets(formation : Formation, count : u32) -> Vec<Vec2> {
+    (0..count).map(|i| {
+        let i = i as f32;
+        match formation {
+            Formation::Line => {
+                let spacing = 480.0 / count as f32;
+                Vec2::new(-240.0 + spacing * (i + 0.5), 340.0)
+            },
+            Formation::Column => Vec2::new(0.0, 340.0 + i * 64.0),
+            Formation::V => {
+                // 0 is the tip, then alternate right/left going further back each pair
+                let rank = ((i + 1.0) / 2.0).floor();
+                let side = if (i as u32).is_multiple_of(2) { 1.0 } else { -1.0 };
+                Vec2::new(side * rank * 60.0, 340.0 + rank * 50.0)
+            },
+            Formation::Flanks => {
+                let side = if (i as u32).is_multiple_of(2) { -1.0 } else { 1.0 };
+                Vec2::new(side * 240.0, 340.0 + (i / 2.0).floor() * 80.0)
+            },
+        }
+    }).collect()
+}
+
+// Sends in the next wave whenever the player's climbed far enough, somewhere in the open part of the cave
+fn wave_spawner(
+    mut commands : Commands,
+    prefabs : Res<Prefabs>,
+    prefab_assets : Res<Assets<Prefab>>,
+    table : Res<WaveTable>,
+    cave : Res<Cave>,
+    mut spawner : ResMut<WaveSpawner>,
+    stats : Res<RunStats>,
+    query : Query<&Transform, With<Scrolling>>,
+) {
+    if table.waves.is_empty() {
+        return;
+    }
+    let camera_y = query.single().unwrap().translation.y;
+    let climbed = stats.climbed();
+    // Gets a third harder every 100m
+    let difficulty = 1.0 + climbed / 300.0;
+    // A table with no length would loop forever without getting any further, so it's at least a metre long,
+    // and no more than one trip through it per tick however far behind we are
+    let length = table.length.max(1.0);
+    for _ in 0..table.waves.len() {
+        let wave = &table.waves[spawner.next];
+        if climbed < wave.at + length * spawner.loops as f32 {
+            break;
+        }
+        // More enemies too, but not as quickly as they toughen up
+        let count = (wave.count as f32 * difficulty.sqrt()).round() as u32;
+        let offsets = formation_offsets(wave.formation, count);
+        // How far either side of the middle of the gap there's room for an enemy, at some height
+        let room = |y : f32| {
+            let (centre, width) = cave.opening_at(camera_y + y);
+            (centre, (width / 2.0 - SPAWN_MARGIN).max(0.0))
+        };
+        // Squash the formation sideways if it doesn't fit through the narrowest bit it spans,
+        // otherwise shuffle it sideways by however much room is left over
+        let narrowest = offsets.iter().map(|offset| room(offset.y).1).fold(f32::MAX, f32::min);
+        let spread = offsets.iter().map(|offset| offset.x.abs()).fold(0.0, f32::max);
+        let squeeze = if spread > narrowest { narrowest / spread } else { 1.0 };
+        let slack = (narrowest - spread * squeeze).max(0.0);
+        let shift = spawner.rng.range(-slack, slack);
+        // Each enemy follows the middle of the gap at its own height, in case the cave bends
+        for offset in offsets {
+            let (centre, room) = room(offset.y);
+            let x = centre + (offset.x * squeeze + shift).clamp(-room, room);
+            let position = Vec3::new(x, camera_y + offset.y, 0.0);
+            spawn_enemy(&mut commands, &prefabs, &prefab_assets, wave.kind, position, difficulty);
+        }
+        spawner.next += 1;
+        if spawner.next == table.waves.len() {
+            spawner.next = 0;
+            spawner.loops += 1;
+        }
+    }
+}
+
+/* Simple system to toggle the Paused state when pressing Escape.
+   It isn't tied to a state on purpose: state-less systems only run once a frame, so the same
+   key press can't pause and then immediately unpause the game. */
+fn pause_handler(
+    mut state : ResMut<State<AppState>>,
+    input : Res<Input<Action>>
+) {
+    if !input.just_pressed(Action::Pause) {
+        return;
+    }
+    // Pushing rather than setting keeps InGame on the stack, frozen, with everything in it untouched.
+    match state.current() {
+        AppState::InGame => state.push(AppState::Paused).ok(),
+        AppState::Paused => state.pop().ok(),
+        _ => None,
+    };
+}
+
+// Shows how the run went. The game's entities are gone by now, so everything comes from RunStats.
+fn setup_game_over(
+    mut commands : Commands,
+    ui : Res<UiAssets>,
+    stats : Res<RunStats>,
+    score : Res<Score>,
+    seed : Res<RunSeed>,
+    mut selection : ResMut<MenuSelection>,
+) {
+    let seconds = stats.time as u32;
+    commands.spawn_bundle(UiCameraBundle::default())
+        .insert(GameOverScoped);
+    commands.spawn_bundle(screen_node(&ui))
+        .insert(GameOverScoped)
+        .with_children(|parent| {
+            spawn_text(parent, &ui, "Game Over", 64.0);
+            spawn_text(parent, &ui, &format!("Depth reached: {:.0}m", stats.depth()), 28.0);
+            spawn_text(parent, &ui, &format!("Score: {}", score.0), 28.0);
+            spawn_text(parent, &ui, &format!("Enemies killed: {}", stats.kills), 28.0);
+            spawn_text(parent, &ui, &format!("Time survived: {}:{:02}", seconds / 60, seconds % 60), 28.0);
+            // So a good cave can be played again with --seed
+            spawn_text(parent, &ui, &format!("Seed: {}", seed.0), 20.0);
+            spawn_button(parent, &ui, "Retry", ButtonAction::Restart, 0);
+            spawn_button(parent, &ui, "Menu", ButtonAction::ToMenu, 1);
+        });
+    selection.0 = 0;
+}
+
+fn game_over(
+    mut state : ResMut<State<AppState>>,
+    input : Res<Input<Action>>,
+    mut selection : ResMut<MenuSelection>,
+    buttons : Query<(&Interaction, ChangeTrackers<Interaction>, &MenuButton)>,
+) {
+    match navigate_buttons(&input, &mut selection, buttons.iter()) {
+        Some(ButtonAction::Restart) => {
+            state.set(AppState::StartGame).ok();
+        },
+        Some(ButtonAction::ToMenu) => {
+            state.set(AppState::Menu).ok();
+        },
+        _ => (),
+    }
+}
+
+// Remove the Game Over screen
+fn clean_game_over(
+    mut commands : Commands,
+    query : Query<Entity, With<GameOverScoped>>,
+) {
+    despawn_scoped(&mut commands, &query);
+}
+
+// Simple system to move the camera up a little bit
+fn scroll_camera(mut query : Query<&mut Transform, With<Scrolling>>) {
+    let mut transform = query.single_mut().unwrap();
+    transform.translation.y += 30.0 * STEP;
+}
+
+// Keeps RunStats up to date while playing
+fn track_run(
+    mut stats : ResMut<RunStats>,
+    query : Query<&Transform, With<Scrolling>>,
+) {
+    stats.time += STEP;
+    if let Ok(transform) = query.single() {
+        stats.height = transform.translation.y;
+    }
+}
+
+
+ // Sub-section, ENEMY AI!!!!! :vomitting_face:
+// Eugh
+
+// Chasers steer toward the player at full speed, but can only turn so fast
+fn chaser_ai(
+    mut set : QuerySet<(
+        Query<(&mut Transform, &Speed, &Health, &EnemyAI, &mut Steering, &mut Direction)>,
+        Query<&Transform, With<Player>>,
+        Query<&Transform, With<Scrolling>>,
+    )>,
+) {
+    // The player's corpse gets despawned right before Game Over, so there might not be anyone to chase.
+    let player_pos = match set.q1().single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+    let camera_y = set.q2().single().unwrap().translation.y;
+    for (mut transform, speed, health, ai_type, mut steering, mut facing) in set.q0_mut().iter_mut() {
+        if health.hp <= 0 || *ai_type != EnemyAI::Chaser {
+            continue;
+        }
+        let to_player = (player_pos - transform.translation).truncate();
+        if to_player != Vec2::ZERO {
+            // Wrap the difference into -PI..PI so we always turn the short way round
+            let wanted = to_player.y.atan2(to_player.x);
+            let turn = (wanted - steering.heading + PI).rem_euclid(TAU) - PI;
+            let max_turn = steering.turn_rate * STEP;
+            steering.heading += turn.min(max_turn).max(-max_turn);
+        }
+        let heading = Vec2::new(steering.heading.cos(), steering.heading.sin());
+        transform.translation += (heading * speed.speed * STEP).extend(0.0);
+
+        facing.direction = DirectionEnum::from_vec(heading);
+
+        // Same box as the player, minus the top edge so chasers can still swoop in from above the screen
+        transform.translation.x = transform.translation.x.clamp(-276.0, 276.0);
+        transform.translation.y = transform.translation.y.max(-276.0 + camera_y);
+    }
+}
+
+// Gunners keep their distance, turn to face the player and take pot shots at them
+fn gunner_ai(
+    mut commands : Commands,
+    prefab_assets : Res<Assets<Prefab>>,
+    mut set : QuerySet<(
+        Query<(&mut Transform, &Speed, &Health, &EnemyAI, &mut Direction, &mut Shooter)>,
+        Query<&Transform, With<Player>>,
+    )>,
+) {
+    let player_pos = match set.q1().single() {
+        Ok(transform) => transform.translation,
+        Err(_) => return,
+    };
+    for (mut transform, speed, health, ai_type, mut facing, mut shooter) in set.q0_mut().iter_mut() {
+        if health.hp <= 0 || *ai_type != EnemyAI::Gunner {
+            continue;
+        }
+        let to_player = (player_pos - transform.translation).truncate();
+        let distance = to_player.length();
+
+        // Sprites only come in four directions, so face whichever axis the player is mostly along
+        facing.direction = DirectionEnum::from_vec(to_player);
+
+        // Close in when too far, back off when the player gets too close, otherwise hold still
+        if distance > 0.0 {
+            let preferred = 220.0;
+            let step = if distance > preferred + 20.0 {
+                1.0
+            } else if distance < preferred - 20.0 {
+                -1.0
+            } else {
+                0.0
+            };
+            transform.translation += (to_player / distance * step * speed.speed * STEP).extend(0.0);
+            transform.translation.x = transform.translation.x.clamp(-276.0, 276.0);
+        }
+
+        shooter.time_out -= STEP;
+        if shooter.time_out <= 0.0 {
+            shooter.time_out = shooter.cooldown();
+            // Bullets on the other hand go straight for wherever the player is right now
+            spawn_bullet(&mut commands, &prefab_assets, &shooter, transform.translation, to_player);
+        }
+    }
+}
+
+// Plugins
+/*
+pub struct Thingy;
+
+impl Plugin for Thingy {
+    fn build(&self, app : &mut AppBuilder) {
+        app.enter
+
+        ;
+    }
+}
+*/
+
+ // Simple plugin for the Game loop.
+// Scratch that, quite complex.
+
+pub struct Game;
+
+// Everything a run needs, minus the menus and the window. Needs the State<AppState> from add_state() too.
+pub struct GamePlugins;
+
+impl PluginGroup for GamePlugins {
+    fn build(&mut self, group : &mut PluginGroupBuilder) {
+        group
+            .add(ActionPlugin)
+            .add(AsepritePlugin)
+            .add(PrefabPlugin)
+            .add(TimestepPlugin)
+            .add(ReplayPlugin)
+            .add(TerrainPlugin)
+            .add(Game);
+    }
+}
+
+impl Plugin for Game {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            // The HUD, pause and Game Over screens need these too, not just the menus
+            .init_resource::<UiAssets>()
+            .init_resource::<MenuSelection>()
+            .init_resource::<RunStats>()
+            .init_resource::<Score>()
+            .add_event::<DamageEvent>()
+            .init_resource::<WaveTable>()
+            .init_resource::<WaveSpawner>()
+            // Startup game
+            .add_system_set(SystemSet::on_enter(AppState::StartGame)
+                .with_system(setup_game.system().after("seed"))
+                .with_system(setup_hud.system())
+            )
+            /* Run game. Everything that moves or counts down runs on the fixed tick (see timestep.rs), which only
+               happens InGame. Each tick is one strict pipeline, so the same input always plays out the same way:
+               snapshot, controls (TickInput), movement, ai, knockback, terrain (terrain.rs), collision, damage, death,
+               then streaming the cave in and out (terrain.rs again). Systems inside a step that touch the same
+               things get chained up too. */
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("movement")
+                .after("tick_input")
+                .with_system(scroll_camera.system().label("scroll_camera"))
+                .with_system(track_run.system().label("track_run").after("scroll_camera"))
+                .with_system(wave_spawner.system().label("wave_spawner").after("track_run"))
+                .with_system(movement.system().label("move_player").after("wave_spawner"))
+                .with_system(controllable_shooting.system().label("player_shooting").after("move_player"))
+                .with_system(homing.system().label("homing").after("player_shooting"))
+                .with_system(bullet_mover.system().after("homing"))
+                .with_system(expire.system())
+                .with_system(tick_iframes.system())
+                .with_system(hurt_flash.system())
+            )
+            // Enemy AI
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("ai")
+                .after("movement")
+                .with_system(chaser_ai.system().label("chaser_ai"))
+                .with_system(gunner_ai.system().after("chaser_ai"))
+            )
+            .add_system_to_stage(FixedUpdate, knockback.system().label("knockback").after("ai"))
+            // Collision
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("collision")
+                .after("terrain")
+                .with_system(bullet_collision.system().label("bullet_collision"))
+                .with_system(contact_collision.system().after("bullet_collision"))
+                .with_system(collect_pickups.system())
+            )
+            // Getting hurt. DamageEvents get read the same tick they're sent, some frames don't tick at all
+            // and events only stick around for a couple of frames.
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("damage")
+                .after("collision")
+                .with_system(hurt.system())
+                .with_system(weapon_damage.system().label("weapon_damage"))
+                .with_system(apply_weapon.system().after("weapon_damage"))
+            )
+            // Death
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("death")
+                .after("damage")
+                .with_system(death.system())
+                .with_system(player_death.system())
+            )
+            // Pause screen
+            .add_system(pause_handler.system())
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(setup_pause.system()))
+            .add_system_set(SystemSet::on_update(AppState::Paused).with_system(pause.system()))
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(clean_pause.system()))
+            // HUD
+            .add_system_set(SystemSet::on_update(AppState::InGame)
+                .with_system(update_health_bar.system())
+                .with_system(update_depth_text.system())
+                .with_system(update_score_text.system())
+                .with_system(update_weapon_text.system())
+            )
+            // Animation. It's only for looks, so it goes by frame time, and the ticks have already turned everything around.
+            .add_system_set(SystemSet::on_update(AppState::InGame)
+                .with_system(animate.system())
+            )
+            // Pausing pushes a state instead of leaving InGame, so this only runs when the game's actually over
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(clean_game.system()))
+            // Game Over
+            .add_system_set(SystemSet::on_enter(AppState::GameOver)
+                .with_system(setup_game_over.system())
+            )
+            .add_system_set(SystemSet::on_exit(AppState::GameOver)
+                .with_system(clean_game_over.system())
+            )
+            .add_system_set(SystemSet::on_update(AppState::GameOver).with_system(game_over.system()));
+    }
+}
+
+// Simple plugin for the Menu
+// Also owns the button stuff the other screens share.
+
+pub struct Menu;
+
+impl Plugin for Menu {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .init_resource::<UiAssets>()
+            .add_system(button_visuals.system())
+            .add_system_set(SystemSet::on_enter(AppState::Menu)
+                .with_system(setup_menu.system())
+            )
+            .add_system_set(SystemSet::on_update(AppState::Menu).with_system(menu.system()))
+            .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(clean_menu.system()))
+            // Options
+            .init_resource::<Rebinding>()
+            .add_system_set(SystemSet::on_enter(AppState::Options)
+                .with_system(setup_options.system())
+            )
+            .add_system_set(SystemSet::on_update(AppState::Options)
+                .with_system(options.system())
+                .with_system(update_binding_labels.system())
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Options).with_system(clean_options.system()));
+    }
+}
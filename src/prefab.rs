@@ -0,0 +1,392 @@
+/*
+
+Prefabs! Turns out you CAN have a stored entity that gets spawned over n over,
+you just have to write it down in a file first. They live in assets/prefabs/ as RON files
+and get spawned with commands.spawn_prefab(handle, transform) from any system.
+
+*/
+
+use std::{collections::HashMap, f32::consts::FRAC_PI_2, path::PathBuf};
+
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, AssetPath, LoadContext, LoadState, LoadedAsset},
+    ecs::system::{Command, EntityCommands},
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use super::{
+    aseprite::Aseprite,
+    animation::{Animation, AnimationState, Sequence},
+    timestep::Interpolated,
+    Bullet, Collider, ContactDamage, Controllable, Damage, Direction, DirectionEnum, Enemy, EnemyAI, Expire,
+    Faction, FactionEnum, Health, Hitbox, IFrames, Pickup, Player, Points, Shooter, Speed, Steering,
+};
+
+// Which marker component(s) the prefab gets
+#[derive(Copy, Clone, Deserialize)]
+pub enum Role {
+    Player, // Also makes it Controllable
+    Enemy,
+    Bullet,
+    Pickup,
+}
+
+// A sprite sheet cut up into a grid of equally sized frames, numbered left to right then top to bottom
+#[derive(Clone, Deserialize)]
+pub struct SheetDef {
+    image : String,
+    tile : (f32, f32), // Size of one frame in the image, in pixels
+    columns : usize,
+    rows : usize,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ShooterDef {
+    bullet : String, // Path to the bullet's own prefab
+    #[serde(default)]
+    muzzle : Option<f32>, // How far in front of the shooter bullets appear, 60 if left out
+    #[serde(default)]
+    shots : Option<u32>, // Bullets fired at once, 1 if left out
+    #[serde(default)]
+    spread : Option<f32>, // Radians between the outermost of those bullets
+    cooldown : f32, // Seconds between shots
+}
+
+/* Everything an entity can be made of. Only role, size and collider are required, plus either a sprite
+   or a sheet to draw. Anything else left out of the file just means the entity doesn't get that component. */
+#[derive(Clone, Deserialize, TypeUuid)]
+#[uuid = "c3a3f0de-6a0b-4d6c-9a53-0f0e6f6d2f41"]
+pub struct Prefab {
+    role : Role,
+    #[serde(default)]
+    sprite : Option<String>, // A single image, for things that don't animate
+    size : (f32, f32), // How big it's drawn and how big its Hitbox is
+    collider : Collider,
+    #[serde(default)]
+    tint : Option<(f32, f32, f32)>, // Multiplied into every sprite, handy for telling apart things that share art
+    #[serde(default)]
+    sheet : Option<SheetDef>,
+    #[serde(default)]
+    animations : Option<HashMap<AnimationState, Sequence>>, // Frames out of the sheet for each state, see animation.rs
+    #[serde(default)]
+    aseprite : Option<String>, // Animate from an .aseprite file's tags instead of a sheet, see aseprite.rs
+    #[serde(default)]
+    faction : Option<FactionEnum>,
+    #[serde(default)]
+    direction : Option<DirectionEnum>,
+    #[serde(default)]
+    pub health : Option<i16>,
+    #[serde(default)]
+    pub speed : Option<f32>,
+    #[serde(default)]
+    damage : Option<i16>,
+    #[serde(default)]
+    score : Option<u32>, // Points for killing it
+    #[serde(default)]
+    invulnerability : Option<f32>, // Seconds of IFrames after getting hurt
+    #[serde(default)]
+    contact_cooldown : Option<f32>, // Seconds between hits when touching something, see ContactDamage
+    #[serde(default)]
+    shooter : Option<ShooterDef>,
+    #[serde(default)]
+    ai : Option<EnemyAI>,
+    #[serde(default)]
+    turn_rate : Option<f32>, // Radians per second, see Steering
+    #[serde(default)]
+    expire : Option<f32>,
+
+    // Filled in by the loader, so the materials only get made once per prefab rather than once per spawn
+    #[serde(skip)]
+    material : Handle<ColorMaterial>,
+    #[serde(skip)]
+    atlas : Handle<TextureAtlas>,
+    #[serde(skip)]
+    aseprite_file : Handle<Aseprite>,
+    // Untyped, since a Prefab holding a Handle<Prefab> sends rustc round in circles working out if it's Send
+    #[serde(skip)]
+    bullet : Option<HandleUntyped>,
+}
+
+#[derive(Default)]
+pub struct PrefabLoader;
+
+impl AssetLoader for PrefabLoader {
+    fn load<'a>(
+        &'a self,
+        bytes : &'a [u8],
+        load_context : &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut prefab : Prefab = ron::de::from_bytes(bytes)?;
+            if let Some(sprite) = &prefab.sprite {
+                prefab.material = material(load_context, "sprite", sprite, prefab.color());
+            }
+            if let Some(sheet) = &prefab.sheet {
+                let texture_path = AssetPath::new(PathBuf::from(&sheet.image), None);
+                let texture = load_context.get_handle(texture_path.clone());
+                let atlas = TextureAtlas::from_grid(texture, Vec2::new(sheet.tile.0, sheet.tile.1), sheet.columns, sheet.rows);
+                prefab.atlas = load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas).with_dependency(texture_path));
+            }
+            let mut dependencies = Vec::new();
+            if let Some(aseprite) = &prefab.aseprite {
+                let aseprite_path = AssetPath::new(PathBuf::from(aseprite), None);
+                prefab.aseprite_file = load_context.get_handle(aseprite_path.clone());
+                dependencies.push(aseprite_path);
+            }
+            // Bullets are prefabs of their own, so load whichever one this shooter fires alongside it
+            if let Some(shooter) = &prefab.shooter {
+                let bullet_path = AssetPath::new(PathBuf::from(&shooter.bullet), None);
+                prefab.bullet = Some(load_context.get_handle::<_, Prefab>(bullet_path.clone()).clone_untyped());
+                dependencies.push(bullet_path);
+            }
+            load_context.set_default_asset(LoadedAsset::new(prefab).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["prefab"]
+    }
+}
+
+impl Prefab {
+    // The tint as a Color, plain white (so no change) if there isn't one
+    fn color(&self) -> Color {
+        let (r, g, b) = self.tint.unwrap_or((1.0, 1.0, 1.0));
+        Color::rgb(r, g, b)
+    }
+}
+
+// Makes a ColorMaterial out of one of the prefab's textures, stored as a labeled sub-asset of the prefab
+fn material(load_context : &mut LoadContext, label : &str, path : &str, color : Color) -> Handle<ColorMaterial> {
+    let texture_path = AssetPath::new(PathBuf::from(path), None);
+    let texture = load_context.get_handle(texture_path.clone());
+    load_context.set_labeled_asset(
+        label,
+        LoadedAsset::new(ColorMaterial::modulated_texture(texture, color)).with_dependency(texture_path),
+    )
+}
+
+// Fills an already spawned (empty) entity in with everything the prefab describes
+struct InsertPrefab {
+    entity : Entity,
+    prefab : Handle<Prefab>,
+    transform : Transform,
+}
+
+impl Command for InsertPrefab {
+    fn write(self : Box<Self>, world : &mut World) {
+        // Cloned so we're not holding on to the Assets resource while poking at the entity
+        let prefab = match world.get_resource::<Assets<Prefab>>().and_then(|prefabs| prefabs.get(&self.prefab)) {
+            Some(prefab) => prefab.clone(),
+            None => {
+                warn!("Tried to spawn a prefab that isn't loaded yet");
+                return;
+            },
+        };
+        // Where the animation frames come from, if it has any: the atlas, the size of one frame, and every state
+        let animated = if let Some(sheet) = &prefab.sheet {
+            Some((prefab.atlas.clone(), Vec2::new(sheet.tile.0, sheet.tile.1), prefab.animations.clone().unwrap_or_default()))
+        } else if prefab.aseprite.is_some() {
+            match world.get_resource::<Assets<Aseprite>>().and_then(|files| files.get(&prefab.aseprite_file)) {
+                Some(file) => Some((file.atlas.clone(), file.size, file.sequences())),
+                None => {
+                    warn!("Tried to spawn a prefab whose .aseprite file isn't loaded yet");
+                    return;
+                },
+            }
+        } else {
+            None
+        };
+        let size = Vec2::new(prefab.size.0, prefab.size.1);
+        let mut entity = world.entity_mut(self.entity);
+        match animated {
+            // Frames are drawn at their size in the image, so scale them up to the size asked for
+            Some((atlas, tile, sequences)) => {
+                let state = AnimationState::facing(prefab.direction.unwrap_or(DirectionEnum::Down));
+                let animation = Animation::new(sequences, state);
+                let mut transform = self.transform;
+                transform.scale = Vec3::new(size.x / tile.x, size.y / tile.y, 1.0);
+                entity.insert_bundle(SpriteSheetBundle {
+                    sprite : TextureAtlasSprite {
+                        color : prefab.color(),
+                        index : animation.first_frame(),
+                        ..Default::default()
+                    },
+                    texture_atlas : atlas,
+                    transform,
+                    ..Default::default()
+                });
+                entity.insert(animation);
+            },
+            None => {
+                entity.insert_bundle(SpriteBundle {
+                    material : prefab.material.clone(),
+                    sprite : Sprite::new(size),
+                    transform : self.transform,
+                    ..Default::default()
+                });
+            },
+        }
+        entity.insert(Hitbox { size });
+        // Everything spawned from a prefab moves around on the fixed tick, so draw it smoothly between ticks
+        entity.insert(Interpolated::default());
+        match prefab.role {
+            Role::Player => entity.insert(Player).insert(Controllable),
+            Role::Enemy => entity.insert(Enemy),
+            Role::Bullet => entity.insert(Bullet),
+            Role::Pickup => entity.insert(Pickup),
+        };
+        entity.insert(prefab.collider);
+        if let Some(faction) = prefab.faction {
+            entity.insert(Faction { faction });
+        }
+        if let Some(direction) = prefab.direction {
+            entity.insert(Direction { direction });
+        }
+        if let Some(hp) = prefab.health {
+            entity.insert(Health { hp, max_hp : hp });
+        }
+        if let Some(speed) = prefab.speed {
+            entity.insert(Speed { speed });
+        }
+        if let Some(damage) = prefab.damage {
+            entity.insert(Damage { damage });
+        }
+        if let Some(duration) = prefab.invulnerability {
+            entity.insert(IFrames {
+                duration,
+                time : 0.0,
+            });
+        }
+        if let Some(points) = prefab.score {
+            entity.insert(Points { points });
+        }
+        if let Some(cooldown) = prefab.contact_cooldown {
+            entity.insert(ContactDamage {
+                time_out : 0.0,
+                max_time_out : cooldown,
+            });
+        }
+        if let (Some(shooter), Some(bullet)) = (&prefab.shooter, &prefab.bullet) {
+            entity.insert(Shooter {
+                bullet : bullet.clone().typed(),
+                muzzle : shooter.muzzle.unwrap_or(60.0),
+                shots : shooter.shots.unwrap_or(1).max(1),
+                spread : shooter.spread.unwrap_or(0.0),
+                rate : 1.0,
+                pierce : false,
+                homing : false,
+                time_out : shooter.cooldown,
+                max_time_out : shooter.cooldown,
+            });
+        }
+        if let Some(ai) = prefab.ai {
+            entity.insert(ai);
+        }
+        // Everything that steers starts off heading straight down, toward the player
+        if let Some(turn_rate) = prefab.turn_rate {
+            entity.insert(Steering {
+                heading : -FRAC_PI_2,
+                turn_rate,
+            });
+        }
+        if let Some(time) = prefab.expire {
+            entity.insert(Expire { time });
+        }
+    }
+}
+
+/* commands.spawn_prefab(). The entity is spawned straight away (so you get its id and can insert
+   more stuff on top, like a scoping marker) but only gets filled in when the commands are applied.
+   Anything you insert yourself is applied after the prefab, so it wins. */
+pub trait SpawnPrefabExt<'a> {
+    fn spawn_prefab(&mut self, prefab : Handle<Prefab>, transform : Transform) -> EntityCommands<'a, '_>;
+}
+
+impl<'a> SpawnPrefabExt<'a> for Commands<'a> {
+    fn spawn_prefab(&mut self, prefab : Handle<Prefab>, transform : Transform) -> EntityCommands<'a, '_> {
+        let entity = self.spawn().id();
+        self.add(InsertPrefab {
+            entity,
+            prefab,
+            transform,
+        });
+        self.entity(entity)
+    }
+}
+
+// The prefabs the game spawns by name, plus everything else in assets/prefabs (bullets and such) loaded once at startup
+pub struct Prefabs {
+    pub player : Handle<Prefab>,
+    pub chaser : Handle<Prefab>,
+    pub gunner : Handle<Prefab>,
+    pub pickup : Handle<Prefab>,
+    folder : Vec<HandleUntyped>,
+}
+
+impl FromWorld for Prefabs {
+    fn from_world(world : &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        // Without the folder there's nothing to spawn, so loaded() stays false and the menu just ignores Start
+        let folder = asset_server.load_folder("prefabs").unwrap_or_else(|error| {
+            warn!("Couldn't load assets/prefabs: {}", error);
+            Vec::new()
+        });
+        Prefabs {
+            player : asset_server.load("prefabs/player.prefab"),
+            chaser : asset_server.load("prefabs/chaser.prefab"),
+            gunner : asset_server.load("prefabs/gunner.prefab"),
+            pickup : asset_server.load("prefabs/pickup.prefab"),
+            folder,
+        }
+    }
+}
+
+impl Prefabs {
+    pub fn enemy(&self, kind : EnemyAI) -> Handle<Prefab> {
+        match kind {
+            EnemyAI::Chaser => self.chaser.clone(),
+            EnemyAI::Gunner => self.gunner.clone(),
+        }
+    }
+
+    // Spawning a prefab before it's loaded does nothing, so check this before starting a run
+    pub fn loaded(&self, asset_server : &AssetServer, prefab_assets : &Assets<Prefab>) -> bool {
+        self.unloaded(asset_server, prefab_assets).is_empty()
+    }
+
+    // What's still loading (or failed to, bevy leaves those stuck on Loading too). Includes the prefabs'
+    // .aseprite files, since those have to be there to spawn anything that uses one.
+    pub fn unloaded(&self, asset_server : &AssetServer, prefab_assets : &Assets<Prefab>) -> Vec<String> {
+        if self.folder.is_empty() {
+            return vec!["assets/prefabs".to_string()];
+        }
+        // Bevy doesn't remember which file a handle came from, so prefabs that aren't in yet go nameless
+        let prefabs = self.folder.iter()
+            .filter(|handle| asset_server.get_load_state(handle.id) != LoadState::Loaded)
+            .map(|handle| format!("a prefab ({:?})", asset_server.get_load_state(handle.id)));
+        let aseprites = self.folder.iter()
+            .filter_map(|handle| prefab_assets.get(handle.id))
+            .filter(|prefab| asset_server.get_load_state(&prefab.aseprite_file) != LoadState::Loaded)
+            .filter_map(|prefab| {
+                let state = asset_server.get_load_state(&prefab.aseprite_file);
+                prefab.aseprite.as_ref().map(|path| format!("{} ({:?})", path, state))
+            });
+        prefabs.chain(aseprites).collect()
+    }
+}
+
+pub struct PrefabPlugin;
+
+impl Plugin for PrefabPlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .add_asset::<Prefab>()
+            .init_asset_loader::<PrefabLoader>()
+            .init_resource::<Prefabs>();
+    }
+}
@@ -0,0 +1,309 @@
+/*
+
+Replays. Gameplay doesn't read Input<Action> directly, it reads TickInput, which gets filled in at
+the start of every tick (see timestep.rs) either from the real controls or from a recording.
+Every run is recorded and saved to replays/last.replay when it ends, and running the game with
+--replay <file> plays that file back instead whenever a run starts. If the recording runs out
+before the run does, the player takes over from there (and the rest gets recorded on the end).
+
+Runs are also generated from a seed (see rng.rs), which gets recorded too. It's picked when a run
+starts: a replay's own seed if there's a replay, whatever came after --seed if that was given, or
+a fresh one otherwise. The Game Over screen shows it so good (or buggy) levels can be shared.
+
+Since the simulation only moves on ticks, the same inputs on the same ticks play out the same run.
+Pausing stops the ticks, so pauses don't change anything and aren't replayed, but the Pause action
+still gets recorded so you can tell where someone paused.
+
+The file is a little header (seed, twin stick setting, tick count) and then one TickInput per tick,
+all gzipped. Holding the same thing for ages is most of a run, so it squashes down nicely.
+
+*/
+
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context};
+use bevy::prelude::*;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::{
+    actions::{Action, AimStick, Bindings, MoveStick},
+    argument,
+    timestep::FixedUpdate,
+    AppState,
+};
+
+const MAGIC : &[u8; 4] = b"DIVE";
+const VERSION : u8 = 1;
+const LAST_REPLAY : &str = "replays/last.replay";
+
+// Everything gameplay gets to know about the controls for one tick
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct TickInput {
+    held : u16, // One bit per action, in Action::ALL order
+    pub move_stick : Vec2,
+    pub aim_stick : Vec2,
+    pub twin_stick : bool, // Comes from the Options screen, so it can't change partway through a run
+}
+
+impl TickInput {
+    pub fn pressed(&self, action : Action) -> bool {
+        self.held & action_bit(action) != 0
+    }
+}
+
+fn action_bit(action : Action) -> u16 {
+    let index = Action::ALL.iter().position(|&other| other == action).unwrap();
+    1 << index
+}
+
+// The seed the current run was generated from
+#[derive(Default)]
+pub struct RunSeed(pub u64);
+
+// The seed every run uses instead of a fresh one, from --seed
+#[derive(Default)]
+pub struct FixedSeed(pub Option<u64>);
+
+// Where each run's recording gets saved when it ends, if anywhere
+pub struct SaveReplay(pub Option<PathBuf>);
+
+impl Default for SaveReplay {
+    fn default() -> Self {
+        SaveReplay(Some(PathBuf::from(LAST_REPLAY)))
+    }
+}
+
+// One run's worth of input
+#[derive(Clone, Default)]
+pub struct Recording {
+    pub seed : u64,
+    pub twin_stick : bool,
+    pub ticks : Vec<TickInput>,
+}
+
+impl Recording {
+    pub fn load(path : &Path) -> anyhow::Result<Self> {
+        let mut bytes = Vec::new();
+        GzDecoder::new(File::open(path)?).read_to_end(&mut bytes)?;
+        Recording::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path : &Path) -> anyhow::Result<()> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        encoder.write_all(&self.to_bytes())?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(18 + self.ticks.len() * 18);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(self.twin_stick as u8);
+        bytes.extend_from_slice(&(self.ticks.len() as u32).to_le_bytes());
+        for tick in self.ticks.iter() {
+            bytes.extend_from_slice(&tick.held.to_le_bytes());
+            for value in [tick.move_stick.x, tick.move_stick.y, tick.aim_stick.x, tick.aim_stick.y].iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes : &[u8]) -> anyhow::Result<Self> {
+        ensure!(bytes.len() >= 18 && &bytes[0..4] == MAGIC, "not a replay file");
+        ensure!(bytes[4] == VERSION, "replay version {} isn't supported", bytes[4]);
+        let seed = u64::from_le_bytes(read_array(bytes, 5)?);
+        let twin_stick = bytes[13] != 0;
+        let count = u32::from_le_bytes(read_array(bytes, 14)?) as usize;
+        ensure!(bytes.len() == 18 + count * 18, "replay is cut short or has junk on the end");
+        let mut ticks = Vec::with_capacity(count);
+        for tick in 0..count {
+            let at = 18 + tick * 18;
+            let value = |offset : usize| read_array(bytes, at + offset).map(f32::from_le_bytes);
+            ticks.push(TickInput {
+                held : u16::from_le_bytes(read_array(bytes, at)?),
+                move_stick : Vec2::new(value(2)?, value(6)?),
+                aim_stick : Vec2::new(value(10)?, value(14)?),
+                twin_stick,
+            });
+        }
+        Ok(Recording { seed, twin_stick, ticks })
+    }
+}
+
+fn read_array<const N : usize>(bytes : &[u8], at : usize) -> anyhow::Result<[u8; N]> {
+    let slice = bytes.get(at..at + N).context("replay is cut short")?;
+    let mut array = [0; N];
+    array.copy_from_slice(slice);
+    Ok(array)
+}
+
+// The run being recorded (or played back) right now, plus the file from --replay if there was one
+#[derive(Default)]
+pub struct Replay {
+    pub playback : Option<Recording>,
+    pub recording : Recording,
+    playing : bool, // Still reading from the recording instead of the controls
+    cursor : usize, // Next tick to play back
+}
+
+impl Replay {
+    // Plays back the recording whenever a run starts, if there is one
+    pub fn new(playback : Option<Recording>) -> Self {
+        Replay {
+            playback,
+            ..Default::default()
+        }
+    }
+}
+
+// Nanoseconds since 1970, which is plenty random enough for a seed. Cut down to 8 digits so it's easy to pass around.
+fn new_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64) % 100_000_000
+}
+
+// Every run starts a fresh recording, or winds the playback back to the start. Runs before anything that needs the seed.
+fn start_run(
+    mut replay : ResMut<Replay>,
+    mut seed : ResMut<RunSeed>,
+    fixed_seed : Res<FixedSeed>,
+    bindings : Res<Bindings>,
+) {
+    let replay = &mut *replay;
+    replay.recording = match &replay.playback {
+        Some(playback) => playback.clone(),
+        None => Recording {
+            seed : fixed_seed.0.unwrap_or_else(new_seed),
+            twin_stick : bindings.twin_stick,
+            ticks : Vec::new(),
+        },
+    };
+    replay.playing = replay.playback.is_some();
+    replay.cursor = 0;
+    seed.0 = replay.recording.seed;
+    info!("Starting a run with seed {}", seed.0);
+}
+
+// First thing every tick (after the snapshot), work out what the controls are doing
+fn tick_input(
+    input : Res<Input<Action>>,
+    stick : Res<MoveStick>,
+    aim_stick : Res<AimStick>,
+    mut replay : ResMut<Replay>,
+    mut tick : ResMut<TickInput>,
+) {
+    if replay.playing {
+        if let Some(&recorded) = replay.recording.ticks.get(replay.cursor) {
+            *tick = recorded;
+            replay.cursor += 1;
+            return;
+        }
+        info!("Replay finished after {} ticks, over to you", replay.cursor);
+        replay.playing = false;
+    }
+    let mut held = 0;
+    for &action in Action::ALL.iter() {
+        if input.pressed(action) {
+            held |= action_bit(action);
+        }
+    }
+    *tick = TickInput {
+        held,
+        move_stick : stick.0,
+        aim_stick : aim_stick.0,
+        twin_stick : replay.recording.twin_stick,
+    };
+    replay.recording.ticks.push(*tick);
+}
+
+fn save_run(replay : Res<Replay>, save : Res<SaveReplay>) {
+    let path = match &save.0 {
+        Some(path) => path,
+        None => return,
+    };
+    match replay.recording.save(path) {
+        Ok(()) => info!("Saved {} ticks to {}", replay.recording.ticks.len(), path.display()),
+        Err(error) => warn!("Couldn't save {}: {}", path.display(), error),
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        let playback = argument("--replay").map(PathBuf::from).and_then(|path| match Recording::load(&path) {
+            Ok(playback) => {
+                info!("Playing back {} ({} ticks)", path.display(), playback.ticks.len());
+                Some(playback)
+            },
+            Err(error) => {
+                warn!("Couldn't load replay {}: {}", path.display(), error);
+                None
+            },
+        });
+        let fixed_seed = argument("--seed").and_then(|seed| match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(_) => {
+                warn!("Ignoring --seed {}, seeds are whole numbers", seed);
+                None
+            },
+        });
+        app
+            .insert_resource(Replay::new(playback))
+            .insert_resource(FixedSeed(fixed_seed))
+            .init_resource::<SaveReplay>()
+            .init_resource::<RunSeed>()
+            .init_resource::<TickInput>()
+            .add_system_set(SystemSet::on_enter(AppState::StartGame).with_system(start_run.system().label("seed")))
+            .add_system_to_stage(FixedUpdate, tick_input.system().label("tick_input").after("snapshot"))
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(save_run.system()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_survive_a_round_trip() {
+        let recording = Recording {
+            seed : 12_345_678,
+            twin_stick : true,
+            // Every tick carries the recording's twin stick setting, like tick_input records them
+            ticks : vec![
+                TickInput {
+                    twin_stick : true,
+                    ..Default::default()
+                },
+                TickInput {
+                    held : action_bit(Action::Fire) | action_bit(Action::MoveLeft),
+                    move_stick : Vec2::new(-1.0, 0.0),
+                    aim_stick : Vec2::new(0.25, -0.75),
+                    twin_stick : true,
+                },
+                TickInput {
+                    held : action_bit(Action::Back),
+                    twin_stick : true,
+                    ..Default::default()
+                },
+            ],
+        };
+        let bytes = recording.to_bytes();
+        let loaded = Recording::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.seed, recording.seed);
+        assert_eq!(loaded.twin_stick, recording.twin_stick);
+        assert!(loaded.ticks == recording.ticks);
+        // Anything cut short is an error, not a shorter replay
+        assert!(Recording::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
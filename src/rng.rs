@@ -0,0 +1,53 @@
+/*
+
+Random numbers for level generation. It's SplitMix64, which is tiny, fast and plenty random for
+picking where rocks go. Everything random in a run comes from the run's seed (RunSeed, see
+replay.rs), so the same seed always makes the same level.
+
+Each thing that wants random numbers gets its own stream (see Rng::stream), so the cave doesn't
+come out different just because an extra enemy got spawned first.
+
+*/
+
+#[derive(Clone, Default)]
+pub struct Rng {
+    state : u64,
+}
+
+impl Rng {
+    pub fn new(seed : u64) -> Self {
+        Rng { state : seed }
+    }
+
+    // A generator for one particular thing (like one row of the cave), independent of every other stream from the same seed
+    pub fn stream(seed : u64, stream : u64) -> Self {
+        let mut mixer = Rng::new(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        Rng::new(mixer.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // 0 up to (but not including) 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min : f32, max : f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    // 0 up to (but not including) count
+    pub fn below(&mut self, count : u32) -> u32 {
+        (self.next_u64() % count.max(1) as u64) as u32
+    }
+
+    pub fn chance(&mut self, odds : f32) -> bool {
+        self.next_f32() < odds
+    }
+}
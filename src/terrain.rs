@@ -0,0 +1,329 @@
+/*
+
+The cave. Walls and rocks get streamed in a chunk at a time just above the top of the screen as the
+camera climbs, and thrown away once they've scrolled off the bottom. Everything in here is a plain
+box with Collider::Environment: the player and enemies get pushed back out of them, and bullets
+stop dead when they hit one.
+
+The cave is laid out in rows of tiles. Each row has a gap between the left and right walls that
+wanders from side to side, every so often squeezing down into a narrow passage, and wide parts of
+the cave sometimes have a rock in the middle to dodge around. Some chunks have a weapon pickup
+tucked away in them too, on top of the ones dead enemies drop. The first couple of chunks are left
+open so a run doesn't start with a wall in your face.
+
+All of it comes from the run's seed. The shape of the cave is picked once when the run starts,
+and each row's rocks and each chunk's pickup come from their own stream (see rng.rs), so a row
+always comes out the same no matter when it gets spawned.
+
+*/
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use super::{
+    prefab::{Prefabs, SpawnPrefabExt},
+    replay::RunSeed,
+    rng::Rng,
+    timestep::FixedUpdate,
+    AppState, Bullet, Collider, Expire, GameScoped, Health, Hitbox, Pickup, Player, Scrolling,
+};
+
+const TILE : f32 = 40.0;
+const COLUMNS : i32 = 15; // 15 * 40 = 600, the width of the window
+const CHUNK_ROWS : i32 = 15;
+const OPEN_ROWS : i32 = 30; // Rows at the start with nothing but a thin wall down each side
+const NARROWEST : f32 = 5.0; // Gap in tiles at the tightest point of a passage, the player's only 48 wide
+const WIDEST : f32 = 13.0;
+const BOTTOM : f32 = -300.0; // Where row 0 starts, the bottom of the screen when the camera's at 0
+const PICKUP_ODDS : f32 = 0.3; // Chance of a chunk having a pickup in it
+// Rng::stream numbers, one lot for each thing that gets randomised
+const SHAPE_STREAM : u64 = 1 << 32;
+const ROCK_STREAM : u64 = 2 << 32;
+const PICKUP_STREAM : u64 = 3 << 32;
+
+// A wall or rock
+pub struct Terrain;
+
+// Which row of the cave gets spawned next
+#[derive(Default)]
+pub struct TerrainSpawner {
+    next_row : i32,
+}
+
+// The current run's cave. How often it pinches into a passage and wanders from side to side, and where those start.
+#[derive(Default)]
+pub struct Cave {
+    seed : u64,
+    pinch_rate : f32,
+    pinch_phase : f32,
+    wander_rate : f32,
+    wander_phase : f32,
+}
+
+impl Cave {
+    fn new(seed : u64) -> Self {
+        let mut rng = Rng::stream(seed, SHAPE_STREAM);
+        Cave {
+            seed,
+            pinch_rate : rng.range(0.035, 0.055),
+            pinch_phase : rng.range(0.0, TAU),
+            wander_rate : rng.range(0.05, 0.09),
+            wander_phase : rng.range(0.0, TAU),
+        }
+    }
+
+    // Where the gap is and how wide it is, in tiles, for a row of the cave
+    fn gap(&self, row : i32) -> (f32, f32) {
+        if row < OPEN_ROWS {
+            return (COLUMNS as f32 / 2.0, WIDEST);
+        }
+        let row = (row - OPEN_ROWS) as f32;
+        // Mostly wide open, but cubing the wave makes it pinch in hard for a little while every so often
+        let pinch = ((row * self.pinch_rate + self.pinch_phase).sin() * 0.5 + 0.5).powi(3);
+        let width = WIDEST - (WIDEST - NARROWEST) * pinch;
+        // Wander from side to side, but never so far that the gap runs off the edge
+        let room = (COLUMNS as f32 - width) / 2.0 - 1.0;
+        let centre = COLUMNS as f32 / 2.0 + room * (row * self.wander_rate + self.wander_phase).sin();
+        (centre, width)
+    }
+
+    // The first column of open water and the first column of the right wall
+    fn walls(&self, row : i32) -> (i32, i32) {
+        let (centre, width) = self.gap(row);
+        ((centre - width / 2.0).floor().max(1.0) as i32, (centre + width / 2.0).ceil().min(COLUMNS as f32 - 1.0) as i32)
+    }
+
+    // The column with a rock in it, if this row has one. Only where there's room to get round it on either side.
+    fn rock(&self, row : i32) -> Option<i32> {
+        let (left, right) = self.walls(row);
+        let open = right - left;
+        let mut rng = Rng::stream(self.seed, ROCK_STREAM + row as u64);
+        if row < OPEN_ROWS || open < 9 || !rng.chance(1.0 / 9.0) {
+            return None;
+        }
+        Some(left + 3 + rng.below((open - 6) as u32) as i32)
+    }
+
+    // The middle of the open water and how wide it is, in pixels, at a height. For spawning things where they'll fit.
+    pub fn opening_at(&self, y : f32) -> (f32, f32) {
+        let (left, right) = self.walls(((y - BOTTOM) / TILE).floor().max(0.0) as i32);
+        (column_x(left) + (right - left) as f32 * TILE / 2.0, (right - left) as f32 * TILE)
+    }
+}
+
+pub struct TerrainAssets {
+    wall : Handle<ColorMaterial>,
+    rock : Handle<ColorMaterial>,
+}
+
+impl FromWorld for TerrainAssets {
+    fn from_world(world : &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        TerrainAssets {
+            wall : materials.add(Color::rgb_u8(14, 48, 66).into()),
+            rock : materials.add(Color::rgb_u8(30, 70, 88).into()),
+        }
+    }
+}
+
+// One box of terrain, in tiles. x is the leftmost column, counting from the left edge of the screen.
+struct Block {
+    x : i32,
+    width : i32,
+    rock : bool,
+}
+
+fn row_blocks(cave : &Cave, row : i32) -> Vec<Block> {
+    let (left, right) = cave.walls(row);
+    let mut blocks = vec![
+        Block { x : 0, width : left, rock : false },
+        Block { x : right, width : COLUMNS - right, rock : false },
+    ];
+    if let Some(x) = cave.rock(row) {
+        blocks.push(Block { x, width : 1, rock : true });
+    }
+    blocks
+}
+
+fn row_y(row : i32) -> f32 {
+    BOTTOM + (row as f32 + 0.5) * TILE
+}
+
+// The left edge of a column
+fn column_x(column : i32) -> f32 {
+    -(COLUMNS as f32) * TILE / 2.0 + column as f32 * TILE
+}
+
+fn spawn_row(commands : &mut Commands, assets : &TerrainAssets, cave : &Cave, row : i32) {
+    for block in row_blocks(cave, row) {
+        let size = Vec2::new(block.width as f32 * TILE, TILE);
+        let x = column_x(block.x) + size.x / 2.0;
+        commands.spawn_bundle(SpriteBundle {
+            material : if block.rock { assets.rock.clone() } else { assets.wall.clone() },
+            sprite : Sprite::new(size),
+            // In front of everything else, not that anything should ever be inside it for long
+            transform : Transform::from_xyz(x, row_y(row), 0.5),
+            ..Default::default()
+        })
+            .insert(Terrain)
+            .insert(Collider::Environment)
+            .insert(Hitbox { size })
+            .insert(GameScoped);
+    }
+}
+
+// Maybe puts a pickup somewhere in the open water of a chunk, starting at first_row
+fn spawn_pickup(commands : &mut Commands, prefabs : &Prefabs, cave : &Cave, first_row : i32) {
+    let mut rng = Rng::stream(cave.seed, PICKUP_STREAM + first_row as u64);
+    if first_row < OPEN_ROWS || !rng.chance(PICKUP_ODDS) {
+        return;
+    }
+    let row = first_row + rng.below(CHUNK_ROWS as u32) as i32;
+    let (left, right) = cave.walls(row);
+    let mut column = left + 1 + rng.below((right - left - 2) as u32) as i32;
+    // Rocks always have open water either side of them
+    if cave.rock(row) == Some(column) {
+        column += 1;
+    }
+    let position = Vec3::new(column_x(column) + TILE / 2.0, row_y(row), 0.0);
+    // Kill drops go away after a while, but these wait for the player to get there
+    commands.spawn_prefab(prefabs.pickup.clone(), Transform::from_translation(position))
+        .remove::<Expire>()
+        .insert(GameScoped);
+}
+
+// New run, new cave
+fn reset_terrain(mut spawner : ResMut<TerrainSpawner>, mut cave : ResMut<Cave>, seed : Res<RunSeed>) {
+    *spawner = TerrainSpawner::default();
+    *cave = Cave::new(seed.0);
+}
+
+// Keeps at least a chunk's worth of cave spawned above the top of the screen, a chunk at a time
+fn stream_terrain(
+    mut commands : Commands,
+    assets : Res<TerrainAssets>,
+    prefabs : Res<Prefabs>,
+    cave : Res<Cave>,
+    mut spawner : ResMut<TerrainSpawner>,
+    cameras : Query<&Transform, With<Scrolling>>,
+) {
+    let camera_y = match cameras.single() {
+        Ok(transform) => transform.translation.y,
+        Err(_) => return,
+    };
+    let wanted = camera_y + 300.0 + CHUNK_ROWS as f32 * TILE;
+    while row_y(spawner.next_row) < wanted {
+        for row in spawner.next_row..spawner.next_row + CHUNK_ROWS {
+            spawn_row(&mut commands, &assets, &cave, row);
+        }
+        spawn_pickup(&mut commands, &prefabs, &cave, spawner.next_row);
+        spawner.next_row += CHUNK_ROWS;
+    }
+}
+
+// Anything that's gone right off the bottom of the screen isn't coming back, pickups the player missed included
+fn despawn_terrain(
+    mut commands : Commands,
+    cameras : Query<&Transform, With<Scrolling>>,
+    terrain : Query<(Entity, &Transform), Or<(With<Terrain>, With<Pickup>)>>,
+) {
+    let camera_y = match cameras.single() {
+        Ok(transform) => transform.translation.y,
+        Err(_) => return,
+    };
+    for (entity, transform) in terrain.iter() {
+        if transform.translation.y + TILE / 2.0 < camera_y - 300.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// How far to move box a so it's no longer inside box b, along whichever axis is the shorter way out
+fn push_out(a_pos : Vec3, a_size : Vec2, b_pos : Vec3, b_size : Vec2) -> Option<Vec2> {
+    let offset = (a_pos - b_pos).truncate();
+    let overlap = (a_size + b_size) / 2.0 - offset.abs();
+    if overlap.x <= 0.0 || overlap.y <= 0.0 {
+        return None;
+    }
+    if overlap.x < overlap.y {
+        Some(Vec2::new(if offset.x < 0.0 { -overlap.x } else { overlap.x }, 0.0))
+    } else {
+        Some(Vec2::new(0.0, if offset.y < 0.0 { -overlap.y } else { overlap.y }))
+    }
+}
+
+/* Shoves the player and enemies back out of any terrain they've ended up in. The screen keeps
+   scrolling though, so getting pinned under a rock and dragged off the bottom of the screen
+   squashes you (enemies just get cleared away). */
+fn block_movement(
+    mut commands : Commands,
+    cameras : Query<&Transform, With<Scrolling>>,
+    terrain : Query<(&Transform, &Hitbox), (With<Terrain>, Without<Scrolling>)>,
+    mut movers : Query<(Entity, &mut Transform, &Hitbox, &Collider, &mut Health, Option<&Player>), (Without<Terrain>, Without<Scrolling>)>,
+) {
+    let camera_y = match cameras.single() {
+        Ok(transform) => transform.translation.y,
+        Err(_) => return,
+    };
+    for (entity, mut transform, hitbox, collider, mut health, player) in movers.iter_mut() {
+        if !matches!(collider, Collider::Player | Collider::Enemy) || health.hp <= 0 {
+            continue;
+        }
+        let mut pushed = false;
+        for (block_transform, block_hitbox) in terrain.iter() {
+            if let Some(push) = push_out(transform.translation, hitbox.size, block_transform.translation, block_hitbox.size) {
+                transform.translation += push.extend(0.0);
+                pushed = true;
+            }
+        }
+        if pushed && transform.translation.y < camera_y - 300.0 {
+            if player.is_some() {
+                health.hp = 0;
+            } else {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+// Bullets don't go through rock, and bullet_collision makes sure they don't hit whatever's behind it on the way in
+fn terrain_stops_bullets(
+    mut commands : Commands,
+    terrain : Query<(&Transform, &Hitbox), With<Terrain>>,
+    bullets : Query<(Entity, &Transform, &Hitbox), With<Bullet>>,
+) {
+    for (bullet, bullet_transform, bullet_hitbox) in bullets.iter() {
+        let hit = terrain.iter().any(|(transform, hitbox)| {
+            push_out(bullet_transform.translation, bullet_hitbox.size, transform.translation, hitbox.size).is_some()
+        });
+        if hit {
+            commands.entity(bullet).despawn();
+        }
+    }
+}
+
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .init_resource::<TerrainAssets>()
+            .init_resource::<TerrainSpawner>()
+            .init_resource::<Cave>()
+            .add_system_set(SystemSet::on_enter(AppState::StartGame).with_system(reset_terrain.system().after("seed")))
+            // After everything that moves things around, so nothing's left inside a wall by the time collisions happen
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .label("terrain")
+                .after("knockback")
+                .with_system(block_movement.system().label("block_movement"))
+                .with_system(terrain_stops_bullets.system().after("block_movement"))
+            )
+            // Last thing each tick, once the camera's moved and everything that's going to die has
+            .add_system_set_to_stage(FixedUpdate, SystemSet::new()
+                .after("death")
+                .with_system(stream_terrain.system())
+                .with_system(despawn_terrain.system())
+            );
+    }
+}
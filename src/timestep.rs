@@ -0,0 +1,148 @@
+/*
+
+The game runs on a fixed tick. Everything that moves things around or counts down a timer goes in
+the FixedUpdate stage, which runs as many 1/60th of a second ticks as fit in the time since last
+frame (none, some frames) and uses STEP instead of Time's delta, so the game plays out the same
+no matter the frame rate. Ticks only happen while InGame, so pausing stops them dead.
+
+Since ticks and frames don't line up, anything Interpolated gets drawn partway between where it
+was before the last tick and where it is now. The real position is put back before the next
+frame's ticks (and is what every system sees), the in-between one only exists for rendering.
+
+Tests (and anything else without a real clock) use FixedTime::manual() instead, which only ticks
+when told to with step().
+
+*/
+
+use bevy::{
+    prelude::*,
+    ecs::schedule::ShouldRun,
+    transform::TransformSystem,
+};
+
+use super::AppState;
+
+// Seconds per tick
+pub const STEP : f32 = 1.0 / 60.0;
+// Most time we'll try to catch up on in one frame, so a long hitch doesn't snowball into an even longer one
+const MAX_CATCH_UP : f32 = 0.25;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct FixedUpdate;
+
+#[derive(Default)]
+pub struct FixedTime {
+    accumulator : f32, // Time that's passed but hasn't been ticked through yet
+    alpha : f32,       // How far between the last tick and the next one this frame is, 0 to 1
+    pub ticks : u64,   // Ticks run since the game started
+    manual : bool,     // Ignore the clock and only run the ticks queued up with step()
+    queued : u32,
+}
+
+impl FixedTime {
+    pub fn manual() -> Self {
+        FixedTime {
+            manual : true,
+            ..Default::default()
+        }
+    }
+
+    // Queues up ticks for the next update to run, in manual mode
+    pub fn step(&mut self, ticks : u32) {
+        self.queued += ticks;
+    }
+}
+
+// Drawn between ticks instead of jumping from one to the next
+#[derive(Default)]
+pub struct Interpolated {
+    previous : Vec3, // Where it was before the last tick
+    current : Vec3,  // Where it really is
+    ready : bool,    // False until it's been through a frame, so new things don't slide in from the origin
+}
+
+// The FixedUpdate stage's run criteria. Says yes once per tick that's due, then no until next frame.
+fn fixed_tick(
+    time : Res<Time>,
+    state : Res<State<AppState>>,
+    mut fixed : ResMut<FixedTime>,
+    mut looping : Local<bool>,
+) -> ShouldRun {
+    if *state.current() != AppState::InGame {
+        fixed.accumulator = 0.0;
+        fixed.queued = 0;
+        return ShouldRun::No;
+    }
+    // Nothing's in between manual ticks, so things get drawn right where they are
+    if fixed.manual {
+        if fixed.queued == 0 {
+            fixed.alpha = 1.0;
+            return ShouldRun::No;
+        }
+        fixed.queued -= 1;
+        fixed.ticks += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    // Only add this frame's time on the first check, the rest are us coming back for another tick
+    if !*looping {
+        fixed.accumulator = (fixed.accumulator + time.delta_seconds()).min(MAX_CATCH_UP);
+    }
+    if fixed.accumulator >= STEP {
+        fixed.accumulator -= STEP;
+        fixed.ticks += 1;
+        *looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        fixed.alpha = fixed.accumulator / STEP;
+        *looping = false;
+        ShouldRun::No
+    }
+}
+
+// Puts everything back where it really is before ticking
+fn restore_positions(mut query : Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in query.iter_mut() {
+        if interpolated.ready {
+            transform.translation = interpolated.current;
+        }
+    }
+}
+
+// First thing every tick, remember where things were
+fn snapshot_positions(mut query : Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.previous = transform.translation;
+    }
+}
+
+// Last thing every frame, move things to where they'd be drawn
+fn interpolate_positions(fixed : Res<FixedTime>, mut query : Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        if !interpolated.ready {
+            interpolated.previous = transform.translation;
+            interpolated.ready = true;
+        }
+        interpolated.current = transform.translation;
+        transform.translation = interpolated.previous.lerp(interpolated.current, fixed.alpha);
+    }
+}
+
+pub struct TimestepPlugin;
+
+impl Plugin for TimestepPlugin {
+    fn build(&self, app : &mut AppBuilder) {
+        app
+            .init_resource::<FixedTime>()
+            .add_stage_after(
+                CoreStage::PreUpdate,
+                FixedUpdate,
+                SystemStage::parallel().with_run_criteria(fixed_tick.system()),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, restore_positions.system())
+            .add_system_to_stage(FixedUpdate, snapshot_positions.system().label("snapshot"))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_positions.system().before(TransformSystem::TransformPropagate),
+            );
+    }
+}
@@ -0,0 +1,176 @@
+/*
+
+A whole game with no window and no GPU. MinimalPlugins plus just enough of bevy to load the
+prefabs (assets, input, transforms and the asset types they use), then GamePlugins on top.
+Ticks only happen when step() says so, and input goes in by pressing keys on Input<KeyCode>
+same as the keyboard would. Runs are generated from SEED unless a test asks for another one, so
+the cave comes out the same every time.
+
+*/
+
+#![allow(dead_code)] // Not every test file uses every helper
+
+use std::{thread, time::Duration};
+
+use bevy::{
+    prelude::*,
+    asset::AssetPlugin,
+    input::InputPlugin,
+    render::texture::ImageTextureLoader,
+    text::FontLoader,
+    transform::TransformPlugin,
+};
+use bevy_jam_game::{
+    AppState, Bindings, FixedSeed, FixedTime, GamePlugins, Health, Hitbox, Player, Prefab, Prefabs, Recording,
+    Replay, SaveReplay, Terrain, WaveTable,
+};
+
+pub const SEED : u64 = 1234;
+
+pub struct Harness {
+    pub app : App,
+}
+
+impl Harness {
+    // A run that's already started, with the usual waves of enemies
+    pub fn new() -> Self {
+        Harness::with_waves(WaveTable::default())
+    }
+
+    // A run with nothing but the player in it
+    pub fn empty() -> Self {
+        Harness::with_waves(WaveTable {
+            waves : Vec::new(),
+            length : 150.0,
+        })
+    }
+
+    pub fn with_waves(waves : WaveTable) -> Self {
+        Harness::build(waves, SEED, None)
+    }
+
+    // A run with the usual waves, from some other seed
+    pub fn seeded(seed : u64) -> Self {
+        Harness::build(WaveTable::default(), seed, None)
+    }
+
+    // A run with the usual waves that plays back a recording, same as --replay
+    pub fn replaying(recording : Recording) -> Self {
+        Harness::build(WaveTable::default(), SEED, Some(recording))
+    }
+
+    fn build(waves : WaveTable, seed : u64, playback : Option<Recording>) -> Self {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(TransformPlugin)
+            // Normally the render, sprite and text plugins bring these along
+            .add_asset::<Texture>()
+            .add_asset::<TextureAtlas>()
+            .add_asset::<ColorMaterial>()
+            .add_asset::<Font>()
+            .init_asset_loader::<ImageTextureLoader>()
+            .init_asset_loader::<FontLoader>()
+            .insert_resource(FixedTime::manual())
+            .add_state(AppState::Menu)
+            .add_plugins(GamePlugins)
+            // Whatever's in a local bindings.ron shouldn't change what the tests press
+            .insert_resource(Bindings::default())
+            .insert_resource(waves)
+            .insert_resource(FixedSeed(Some(seed)))
+            .insert_resource(Replay::new(playback))
+            // Tests that reach Game Over would leave a replay lying around in whatever folder they ran from
+            .insert_resource(SaveReplay(None));
+        let mut harness = Harness { app : builder.app };
+        harness.start();
+        harness
+    }
+
+    // Waits for the prefabs to load, then starts a run the same way the title screen's Start button does
+    fn start(&mut self) {
+        let mut unloaded = Vec::new();
+        for _ in 0..1000 {
+            self.app.update();
+            let world = &self.app.world;
+            let asset_server = world.get_resource::<AssetServer>().unwrap();
+            let prefab_assets = world.get_resource::<Assets<Prefab>>().unwrap();
+            unloaded = world.get_resource::<Prefabs>().unwrap().unloaded(asset_server, prefab_assets);
+            if unloaded.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(unloaded.is_empty(), "the prefabs never finished loading, still waiting on {}", unloaded.join(", "));
+        self.app.world.get_resource_mut::<State<AppState>>().unwrap().set(AppState::StartGame).unwrap();
+        for _ in 0..10 {
+            self.app.update();
+            if self.state() == AppState::InGame {
+                return;
+            }
+        }
+        panic!("never got InGame (stuck on {:?})", self.state());
+    }
+
+    // Runs this many ticks, all in one update
+    pub fn step(&mut self, ticks : u32) {
+        self.app.world.get_resource_mut::<FixedTime>().unwrap().step(ticks);
+        self.app.update();
+    }
+
+    pub fn press(&mut self, key : KeyCode) {
+        self.app.world.get_resource_mut::<Input<KeyCode>>().unwrap().press(key);
+    }
+
+    pub fn release(&mut self, key : KeyCode) {
+        self.app.world.get_resource_mut::<Input<KeyCode>>().unwrap().release(key);
+    }
+
+    pub fn state(&self) -> AppState {
+        self.app.world.get_resource::<State<AppState>>().unwrap().current().clone()
+    }
+
+    pub fn player_health(&mut self) -> Health {
+        let health = self.app.world.query_filtered::<&Health, With<Player>>()
+            .iter(&self.app.world)
+            .next()
+            .expect("there's no player");
+        Health { hp : health.hp, max_hp : health.max_hp }
+    }
+
+    pub fn player_position(&mut self) -> Vec3 {
+        self.app.world.query_filtered::<&Transform, With<Player>>()
+            .iter(&self.app.world)
+            .next()
+            .expect("there's no player")
+            .translation
+    }
+
+    // Where every wall and rock is, and how big
+    pub fn terrain(&mut self) -> Vec<(Vec3, Vec2)> {
+        self.app.world.query_filtered::<(&Transform, &Hitbox), With<Terrain>>()
+            .iter(&self.app.world)
+            .map(|(transform, hitbox)| (transform.translation, hitbox.size))
+            .collect()
+    }
+
+    // An extra wall on top of the cave, for when a test needs one somewhere in particular
+    pub fn spawn_wall(&mut self, position : Vec3, size : Vec2) -> Entity {
+        self.app.world.spawn()
+            .insert(Transform::from_translation(position))
+            .insert(Hitbox { size })
+            .insert(Terrain)
+            .id()
+    }
+
+    // Everything this run's input has been so far
+    pub fn recording(&self) -> Recording {
+        self.app.world.get_resource::<Replay>().unwrap().recording.clone()
+    }
+
+    // How many entities have a T
+    pub fn count<T : bevy::ecs::component::Component>(&mut self) -> usize {
+        self.app.world.query_filtered::<Entity, With<T>>().iter(&self.app.world).count()
+    }
+}
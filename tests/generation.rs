@@ -0,0 +1,40 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_jam_game::RunSeed;
+use common::{Harness, SEED};
+
+// Where everything in the run is, in a fixed order so two runs can be compared
+fn layout(harness : &mut Harness) -> Vec<(i32, i32)> {
+    let mut positions : Vec<(i32, i32)> = harness.app.world.query::<&Transform>()
+        .iter(&harness.app.world)
+        .map(|transform| (transform.translation.x.round() as i32, transform.translation.y.round() as i32))
+        .collect();
+    positions.sort();
+    positions
+}
+
+#[test]
+fn the_run_uses_the_fixed_seed() {
+    let harness = Harness::new();
+    assert_eq!(harness.app.world.get_resource::<RunSeed>().unwrap().0, SEED);
+}
+
+#[test]
+fn the_same_seed_makes_the_same_level() {
+    let mut first = Harness::seeded(42);
+    let mut second = Harness::seeded(42);
+    // Long enough for the first waves to come in, not so long the player might be dead
+    first.step(300);
+    second.step(300);
+    assert_eq!(layout(&mut first), layout(&mut second));
+}
+
+#[test]
+fn different_seeds_make_different_levels() {
+    let mut first = Harness::seeded(42);
+    let mut second = Harness::seeded(43);
+    first.step(300);
+    second.step(300);
+    assert_ne!(layout(&mut first), layout(&mut second));
+}
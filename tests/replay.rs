@@ -0,0 +1,25 @@
+mod common;
+
+use bevy::prelude::*;
+use common::Harness;
+
+#[test]
+fn playing_back_a_recording_reproduces_the_run() {
+    let mut original = Harness::new();
+    original.press(KeyCode::Z);
+    original.press(KeyCode::Right);
+    original.step(90);
+    original.release(KeyCode::Right);
+    original.press(KeyCode::Up);
+    original.step(120);
+    original.release(KeyCode::Z);
+    original.press(KeyCode::Left);
+    original.step(90);
+    let recording = original.recording();
+    assert_eq!(recording.ticks.len(), 300);
+
+    let mut replayed = Harness::replaying(recording);
+    replayed.step(300);
+    assert_eq!(replayed.player_position(), original.player_position());
+    assert_eq!(replayed.player_health().hp, original.player_health().hp);
+}
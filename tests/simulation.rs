@@ -0,0 +1,85 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_jam_game::{AppState, Bullet, Enemy, EnemyAI, Faction, FactionEnum, Formation, Health, Player, Wave, WaveTable, STEP};
+use common::Harness;
+
+#[test]
+fn holding_fire_for_a_second_spawns_ten_bullets() {
+    let mut harness = Harness::empty();
+    // Let the gun cool off from when it was spawned first
+    harness.step(60);
+    harness.press(KeyCode::Z);
+    harness.step(60);
+    let bullets = harness.app.world.query_filtered::<&Faction, With<Bullet>>()
+        .iter(&harness.app.world)
+        .filter(|faction| matches!(faction.faction, FactionEnum::Players))
+        .count();
+    assert_eq!(bullets, 10);
+    assert_eq!(harness.state(), AppState::InGame);
+}
+
+#[test]
+fn nothing_shoots_without_fire_held() {
+    let mut harness = Harness::empty();
+    harness.step(120);
+    assert_eq!(harness.count::<Bullet>(), 0);
+}
+
+#[test]
+fn holding_right_moves_the_player_right() {
+    let mut harness = Harness::empty();
+    let start = harness.player_position();
+    harness.press(KeyCode::Right);
+    harness.step(30);
+    harness.release(KeyCode::Right);
+    harness.step(30);
+    let moved = harness.player_position() - start;
+    // The player's speed is 300 in player.prefab
+    assert!((moved.x - 300.0 * 30.0 * STEP).abs() < 0.01, "moved {:?}", moved);
+    assert!(moved.y.abs() < 0.01, "moved {:?}", moved);
+}
+
+#[test]
+fn chaser_reaching_the_player_deals_damage() {
+    // The first wave is a single chaser, straight away
+    let mut harness = Harness::new();
+    let start = harness.player_health();
+    for _ in 0..60 {
+        harness.step(10);
+        if harness.player_health().hp < start.hp {
+            assert_eq!(harness.state(), AppState::InGame);
+            return;
+        }
+    }
+    panic!("the chaser never got to the player in 10 seconds");
+}
+
+#[test]
+fn running_out_of_health_ends_the_run() {
+    let mut harness = Harness::empty();
+    for mut health in harness.app.world.query_filtered::<&mut Health, With<Player>>().iter_mut(&mut harness.app.world) {
+        health.hp = 0;
+    }
+    // The corpse hangs around for a couple of seconds first
+    harness.step(180);
+    // One more update to actually switch over
+    harness.app.update();
+    assert_eq!(harness.state(), AppState::GameOver);
+}
+
+#[test]
+fn a_wave_table_with_no_length_goes_through_once_per_metre() {
+    // Everything at 0m in a table that's 0m long, which used to go round and round forever in one tick
+    let wave = || Wave { at : 0.0, kind : EnemyAI::Chaser, count : 1, formation : Formation::Line };
+    let mut harness = Harness::with_waves(WaveTable {
+        waves : vec![wave(), wave(), wave()],
+        length : 0.0,
+    });
+    // The camera climbs 30 pixels a second, so that's nowhere near a metre yet
+    harness.step(10);
+    assert_eq!(harness.count::<Enemy>(), 3);
+    // Past the first metre it's had time for exactly one more trip
+    harness.step(15);
+    assert_eq!(harness.count::<Enemy>(), 6);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_jam_game::{Bullet, Enemy, EnemyAI, Formation, Health, Hitbox, Wave, WaveTable, STEP};
+use common::Harness;
+
+// Whether two boxes overlap by more than rounding error, touching doesn't count
+fn overlapping(a_pos : Vec3, a_size : Vec2, b_pos : Vec3, b_size : Vec2) -> bool {
+    let overlap = (a_size + b_size) / 2.0 - (a_pos - b_pos).truncate().abs();
+    overlap.x > 0.01 && overlap.y > 0.01
+}
+
+#[test]
+fn walls_push_the_player_back_out() {
+    let mut harness = Harness::empty();
+    let start = harness.player_position();
+    harness.press(KeyCode::Left);
+    // Plenty of time to get to the left wall and keep pushing into it
+    harness.step(60);
+    let against_wall = harness.player_position();
+    harness.step(30);
+    let position = harness.player_position();
+    assert!(start.x - position.x > 100.0, "only got from {:?} to {:?}", start, position);
+    assert!((position.x - against_wall.x).abs() < 0.01, "kept going from {:?} to {:?}", against_wall, position);
+    // The player's 48 wide in player.prefab
+    for (wall, size) in harness.terrain() {
+        assert!(!overlapping(position, Vec2::splat(48.0), wall, size), "player at {:?} is inside the wall at {:?}", position, wall);
+    }
+}
+
+#[test]
+fn bullets_stop_at_walls_without_hurting_whatever_is_behind_them() {
+    // One chaser, which comes straight for the player and ends up pressed against the far side of the wall
+    let mut harness = Harness::with_waves(WaveTable {
+        waves : vec![Wave { at : 0.0, kind : EnemyAI::Chaser, count : 1, formation : Formation::Line }],
+        length : 1000.0,
+    });
+    /* Thin, and placed so the tick a bullet first gets into the wall it's already poking out the other side
+       into the chaser. Bullets start on the player and go 500 pixels a second (player_bullet.prefab). */
+    let wall_size = Vec2::new(300.0, 4.0);
+    let wall_position = harness.player_position() + Vec3::new(0.0, 98.0, 0.0);
+    harness.spawn_wall(wall_position, wall_size);
+    harness.step(120);
+    let chaser = harness.app.world.query_filtered::<(&Transform, &Health), With<Enemy>>()
+        .iter(&harness.app.world)
+        .map(|(transform, health)| (transform.translation, health.hp))
+        .next()
+        .expect("the chaser never spawned");
+    assert!(chaser.0.y > wall_position.y, "the chaser got past the wall to {:?}", chaser.0);
+
+    harness.press(KeyCode::Z);
+    let mut fired = HashSet::new();
+    let mut in_wall : Vec<Entity> = Vec::new();
+    for _ in 0..60 {
+        harness.step(1);
+        // Whatever was in the wall last tick is gone now
+        for bullet in in_wall.drain(..) {
+            assert!(harness.app.world.get_entity(bullet).is_none(), "a bullet was still around a tick after hitting the wall");
+        }
+        let bullets : Vec<(Entity, Vec3, Vec2)> = harness.app.world.query_filtered::<(Entity, &Transform, &Hitbox), With<Bullet>>()
+            .iter(&harness.app.world)
+            .map(|(bullet, transform, hitbox)| (bullet, transform.translation, hitbox.size))
+            .collect();
+        fired.extend(bullets.iter().map(|(bullet, _, _)| *bullet));
+        in_wall = bullets.into_iter()
+            .filter(|(_, position, size)| overlapping(*position, *size, wall_position, wall_size))
+            .map(|(bullet, _, _)| bullet)
+            .collect();
+    }
+    // Bullets last 5 seconds, so the only way any of them are gone already is the wall
+    assert!(fired.len() > harness.count::<Bullet>(), "no bullets ever reached the wall");
+    let health = harness.app.world.query_filtered::<&Health, With<Enemy>>()
+        .iter(&harness.app.world)
+        .next()
+        .expect("the chaser's gone")
+        .hp;
+    assert_eq!(health, chaser.1);
+}
+
+#[test]
+fn terrain_below_the_screen_gets_despawned() {
+    let mut harness = Harness::empty();
+    // The cave only starts streaming in on the first tick
+    harness.step(1);
+    let ticks = 120;
+    // The camera climbs 30 pixels a second and the screen's 600 tall
+    let bottom = 30.0 * STEP * (ticks + 1) as f32 - 300.0;
+    let below = |terrain : &[(Vec3, Vec2)]| terrain.iter().filter(|(position, size)| position.y + size.y / 2.0 < bottom).count();
+    let before = harness.terrain();
+    assert!(below(&before) > 0, "nothing starts low enough to scroll off");
+    harness.step(ticks);
+    let after = harness.terrain();
+    assert_eq!(below(&after), 0);
+    // Fresh cave keeps coming in at the top
+    let top = |terrain : &[(Vec3, Vec2)]| terrain.iter().map(|(position, _)| position.y).fold(f32::MIN, f32::max);
+    assert!(top(&after) >= top(&before));
+}